@@ -0,0 +1,4 @@
+CREATE TABLE roles (
+  mask VARCHAR PRIMARY KEY NOT NULL,
+  role VARCHAR NOT NULL
+)
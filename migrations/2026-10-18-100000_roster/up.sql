@@ -0,0 +1,10 @@
+CREATE TABLE roster (
+  channel VARCHAR NOT NULL,
+  nickname VARCHAR NOT NULL,
+  username VARCHAR,
+  hostname VARCHAR,
+  account VARCHAR,
+  away BOOLEAN NOT NULL DEFAULT 'f',
+  modes VARCHAR NOT NULL DEFAULT '',
+  PRIMARY KEY (channel, nickname)
+)
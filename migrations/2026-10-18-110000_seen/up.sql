@@ -0,0 +1,7 @@
+CREATE TABLE seen (
+  nickname VARCHAR PRIMARY KEY NOT NULL,
+  action VARCHAR NOT NULL,
+  channel VARCHAR,
+  detail VARCHAR,
+  time DATETIME NOT NULL
+)
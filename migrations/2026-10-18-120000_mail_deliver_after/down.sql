@@ -0,0 +1,11 @@
+CREATE TABLE mail_backup (
+  id INTEGER PRIMARY KEY NOT NULL,
+  target VARCHAR NOT NULL,
+  sender VARCHAR NOT NULL,
+  message VARCHAR NOT NULL,
+  sent DATETIME NOT NULL,
+  private BOOLEAN NOT NULL DEFAULT 'f'
+);
+INSERT INTO mail_backup SELECT id, target, sender, message, sent, private FROM mail;
+DROP TABLE mail;
+ALTER TABLE mail_backup RENAME TO mail;
@@ -0,0 +1 @@
+ALTER TABLE mail ADD COLUMN deliver_after DATETIME
@@ -0,0 +1,9 @@
+CREATE TABLE reminders (
+  id INTEGER PRIMARY KEY NOT NULL,
+  creator VARCHAR NOT NULL,
+  target VARCHAR NOT NULL,
+  channel VARCHAR NOT NULL,
+  message VARCHAR NOT NULL,
+  created DATETIME NOT NULL,
+  due DATETIME NOT NULL
+)
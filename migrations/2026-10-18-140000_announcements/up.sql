@@ -0,0 +1,8 @@
+CREATE TABLE announcements (
+  id INTEGER PRIMARY KEY NOT NULL,
+  creator VARCHAR NOT NULL,
+  channel VARCHAR NOT NULL,
+  schedule VARCHAR NOT NULL,
+  message VARCHAR NOT NULL,
+  next_run DATETIME NOT NULL
+)
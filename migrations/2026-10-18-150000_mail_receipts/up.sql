@@ -0,0 +1,2 @@
+ALTER TABLE mail ADD COLUMN request_receipt BOOLEAN NOT NULL DEFAULT 'f';
+ALTER TABLE mail ADD COLUMN receipt BOOLEAN NOT NULL DEFAULT 'f'
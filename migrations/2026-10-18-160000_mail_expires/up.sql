@@ -0,0 +1 @@
+ALTER TABLE mail ADD COLUMN expires DATETIME
@@ -0,0 +1 @@
+DROP TABLE nick_links
@@ -0,0 +1,8 @@
+CREATE TABLE nick_links (
+  nickname VARCHAR PRIMARY KEY NOT NULL,
+  person VARCHAR NOT NULL,
+  account VARCHAR,
+  linked DATETIME NOT NULL
+);
+CREATE INDEX nick_links_person ON nick_links (person);
+CREATE INDEX nick_links_account ON nick_links (account)
@@ -0,0 +1,49 @@
+CREATE TABLE nick_links_unkeyed (
+  nickname VARCHAR PRIMARY KEY NOT NULL,
+  person VARCHAR NOT NULL,
+  account VARCHAR,
+  linked DATETIME NOT NULL
+);
+INSERT INTO nick_links_unkeyed SELECT nickname, person, account, linked FROM nick_links;
+DROP TABLE nick_links;
+ALTER TABLE nick_links_unkeyed RENAME TO nick_links;
+CREATE INDEX nick_links_person ON nick_links (person);
+CREATE INDEX nick_links_account ON nick_links (account);
+
+CREATE TABLE seen_unkeyed (
+  nickname VARCHAR PRIMARY KEY NOT NULL,
+  action VARCHAR NOT NULL,
+  channel VARCHAR,
+  detail VARCHAR,
+  time DATETIME NOT NULL
+);
+INSERT INTO seen_unkeyed SELECT nickname, action, channel, detail, time FROM seen;
+DROP TABLE seen;
+ALTER TABLE seen_unkeyed RENAME TO seen;
+
+CREATE TABLE whois_unkeyed (
+  nickname VARCHAR PRIMARY KEY NOT NULL,
+  description VARCHAR NOT NULL
+);
+INSERT INTO whois_unkeyed SELECT nickname, description FROM whois;
+DROP TABLE whois;
+ALTER TABLE whois_unkeyed RENAME TO whois;
+
+CREATE TABLE mail_backup (
+  id INTEGER PRIMARY KEY NOT NULL,
+  target VARCHAR NOT NULL,
+  sender VARCHAR NOT NULL,
+  message VARCHAR NOT NULL,
+  sent DATETIME NOT NULL,
+  private BOOLEAN NOT NULL DEFAULT 'f',
+  deliver_after DATETIME,
+  request_receipt BOOLEAN NOT NULL DEFAULT 'f',
+  receipt BOOLEAN NOT NULL DEFAULT 'f',
+  expires DATETIME
+);
+INSERT INTO mail_backup
+  SELECT id, target, sender, message, sent, private, deliver_after, request_receipt, receipt,
+    expires
+  FROM mail;
+DROP TABLE mail;
+ALTER TABLE mail_backup RENAME TO mail
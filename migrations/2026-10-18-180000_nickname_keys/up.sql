@@ -0,0 +1,45 @@
+-- Keys are folded with rfc1459 casemapping, the default. They're recomputed at runtime if the
+-- server uses something else.
+ALTER TABLE mail ADD COLUMN target_key VARCHAR NOT NULL DEFAULT '';
+ALTER TABLE mail ADD COLUMN sender_key VARCHAR NOT NULL DEFAULT '';
+UPDATE mail SET
+  target_key = lower(replace(replace(replace(replace(target, '[', '{'), ']', '}'), '\', '|'), '~', '^')),
+  sender_key = lower(replace(replace(replace(replace(sender, '[', '{'), ']', '}'), '\', '|'), '~', '^'));
+CREATE INDEX mail_target_key ON mail (target_key);
+CREATE INDEX mail_sender_key ON mail (sender_key);
+
+CREATE TABLE whois_keyed (
+  nickname VARCHAR NOT NULL,
+  description VARCHAR NOT NULL,
+  nickname_key VARCHAR PRIMARY KEY NOT NULL
+);
+INSERT OR REPLACE INTO whois_keyed SELECT nickname, description, lower(replace(replace(replace(replace(nickname, '[', '{'), ']', '}'), '\', '|'), '~', '^')) FROM whois;
+DROP TABLE whois;
+ALTER TABLE whois_keyed RENAME TO whois;
+
+CREATE TABLE seen_keyed (
+  nickname VARCHAR NOT NULL,
+  action VARCHAR NOT NULL,
+  channel VARCHAR,
+  detail VARCHAR,
+  time DATETIME NOT NULL,
+  nickname_key VARCHAR PRIMARY KEY NOT NULL
+);
+INSERT OR REPLACE INTO seen_keyed
+  SELECT nickname, action, channel, detail, time, lower(replace(replace(replace(replace(nickname, '[', '{'), ']', '}'), '\', '|'), '~', '^')) FROM seen ORDER BY time;
+DROP TABLE seen;
+ALTER TABLE seen_keyed RENAME TO seen;
+
+CREATE TABLE nick_links_keyed (
+  nickname VARCHAR NOT NULL,
+  person VARCHAR NOT NULL,
+  account VARCHAR,
+  linked DATETIME NOT NULL,
+  nickname_key VARCHAR PRIMARY KEY NOT NULL
+);
+INSERT OR REPLACE INTO nick_links_keyed
+  SELECT nickname, lower(replace(replace(replace(replace(person, '[', '{'), ']', '}'), '\', '|'), '~', '^')), account, linked, lower(replace(replace(replace(replace(nickname, '[', '{'), ']', '}'), '\', '|'), '~', '^')) FROM nick_links ORDER BY linked;
+DROP TABLE nick_links;
+ALTER TABLE nick_links_keyed RENAME TO nick_links;
+CREATE INDEX nick_links_person ON nick_links (person);
+CREATE INDEX nick_links_account ON nick_links (account)
@@ -0,0 +1 @@
+DROP TABLE whois_history
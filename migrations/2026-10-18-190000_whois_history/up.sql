@@ -0,0 +1,13 @@
+-- Every version of every description, so that changes can be undone. A NULL description means
+-- it was removed. Existing descriptions become the first version of each.
+CREATE TABLE whois_history (
+  id INTEGER PRIMARY KEY NOT NULL,
+  nickname_key VARCHAR NOT NULL,
+  nickname VARCHAR NOT NULL,
+  description VARCHAR,
+  changed_by VARCHAR NOT NULL,
+  changed DATETIME NOT NULL
+);
+CREATE INDEX whois_history_key ON whois_history (nickname_key, id);
+INSERT INTO whois_history (nickname_key, nickname, description, changed_by, changed)
+  SELECT nickname_key, nickname, description, nickname, CURRENT_TIMESTAMP FROM whois
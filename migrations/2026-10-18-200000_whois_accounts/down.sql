@@ -0,0 +1,8 @@
+CREATE TABLE whois_unowned (
+  nickname VARCHAR NOT NULL,
+  description VARCHAR NOT NULL,
+  nickname_key VARCHAR PRIMARY KEY NOT NULL
+);
+INSERT INTO whois_unowned SELECT nickname, description, nickname_key FROM whois;
+DROP TABLE whois;
+ALTER TABLE whois_unowned RENAME TO whois
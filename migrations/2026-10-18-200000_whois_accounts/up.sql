@@ -0,0 +1,3 @@
+-- The services account a description belongs to, once it has been written by someone
+-- identified. Only that account can change it from then on.
+ALTER TABLE whois ADD COLUMN account VARCHAR
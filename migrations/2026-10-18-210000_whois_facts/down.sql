@@ -0,0 +1,8 @@
+DROP TRIGGER whois_facts_search_delete;
+DROP TRIGGER whois_facts_search_update;
+DROP TRIGGER whois_facts_search_insert;
+DROP TRIGGER whois_search_delete;
+DROP TRIGGER whois_search_update;
+DROP TRIGGER whois_search_insert;
+DROP TABLE whois_search;
+DROP TABLE whois_facts
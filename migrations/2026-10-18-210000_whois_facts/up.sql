@@ -0,0 +1,47 @@
+-- Facts like "likes coffee" that people add about themselves alongside their description.
+CREATE TABLE whois_facts (
+  nickname_key VARCHAR NOT NULL,
+  name VARCHAR NOT NULL,
+  nickname VARCHAR NOT NULL,
+  value VARCHAR NOT NULL,
+  account VARCHAR,
+  PRIMARY KEY (nickname_key, name)
+);
+
+-- Full-text search over descriptions and facts, kept up to date by the triggers below. `field`
+-- is the name of a fact, or empty for a description. Rows are replaced rather than updated in
+-- place, and REPLACE doesn't fire delete triggers, so each insert clears out what it replaces.
+CREATE VIRTUAL TABLE whois_search USING fts5(
+  nickname_key UNINDEXED, nickname UNINDEXED, field UNINDEXED, text
+);
+
+CREATE TRIGGER whois_search_insert AFTER INSERT ON whois BEGIN
+  DELETE FROM whois_search WHERE nickname_key = new.nickname_key AND field = '';
+  INSERT INTO whois_search (nickname_key, nickname, field, text)
+    VALUES (new.nickname_key, new.nickname, '', new.description);
+END;
+CREATE TRIGGER whois_search_update AFTER UPDATE ON whois BEGIN
+  DELETE FROM whois_search WHERE nickname_key = old.nickname_key AND field = '';
+  INSERT INTO whois_search (nickname_key, nickname, field, text)
+    VALUES (new.nickname_key, new.nickname, '', new.description);
+END;
+CREATE TRIGGER whois_search_delete AFTER DELETE ON whois BEGIN
+  DELETE FROM whois_search WHERE nickname_key = old.nickname_key AND field = '';
+END;
+
+CREATE TRIGGER whois_facts_search_insert AFTER INSERT ON whois_facts BEGIN
+  DELETE FROM whois_search WHERE nickname_key = new.nickname_key AND field = new.name;
+  INSERT INTO whois_search (nickname_key, nickname, field, text)
+    VALUES (new.nickname_key, new.nickname, new.name, new.name || ' ' || new.value);
+END;
+CREATE TRIGGER whois_facts_search_update AFTER UPDATE ON whois_facts BEGIN
+  DELETE FROM whois_search WHERE nickname_key = old.nickname_key AND field = old.name;
+  INSERT INTO whois_search (nickname_key, nickname, field, text)
+    VALUES (new.nickname_key, new.nickname, new.name, new.name || ' ' || new.value);
+END;
+CREATE TRIGGER whois_facts_search_delete AFTER DELETE ON whois_facts BEGIN
+  DELETE FROM whois_search WHERE nickname_key = old.nickname_key AND field = old.name;
+END;
+
+INSERT INTO whois_search (nickname_key, nickname, field, text)
+  SELECT nickname_key, nickname, '', description FROM whois
@@ -0,0 +1,2 @@
+DROP TABLE whois_audit;
+DROP TABLE whois_locks
@@ -0,0 +1,18 @@
+-- Descriptions that only owners can change.
+CREATE TABLE whois_locks (
+  nickname_key VARCHAR PRIMARY KEY NOT NULL,
+  locked_by VARCHAR NOT NULL,
+  locked DATETIME NOT NULL
+);
+
+-- Every change an owner makes to someone else's description or facts.
+CREATE TABLE whois_audit (
+  id INTEGER PRIMARY KEY NOT NULL,
+  nickname_key VARCHAR NOT NULL,
+  nickname VARCHAR NOT NULL,
+  action VARCHAR NOT NULL,
+  detail VARCHAR,
+  changed_by VARCHAR NOT NULL,
+  changed DATETIME NOT NULL
+);
+CREATE INDEX whois_audit_key ON whois_audit (nickname_key)
@@ -0,0 +1,2 @@
+DROP TABLE factoid_history;
+DROP TABLE factoids
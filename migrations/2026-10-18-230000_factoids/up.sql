@@ -0,0 +1,24 @@
+-- Infobot-style factoids, like "rust is a systems language". `channel` is the (folded) channel
+-- a factoid was learned in, or empty for one that applies everywhere.
+CREATE TABLE factoids (
+  channel VARCHAR NOT NULL,
+  name VARCHAR NOT NULL,
+  verb VARCHAR NOT NULL,
+  value VARCHAR NOT NULL,
+  created_by VARCHAR NOT NULL,
+  created DATETIME NOT NULL,
+  locked BOOLEAN NOT NULL DEFAULT 0,
+  PRIMARY KEY (channel, name)
+);
+
+-- Every version of every factoid. A NULL value means it was forgotten.
+CREATE TABLE factoid_history (
+  id INTEGER PRIMARY KEY NOT NULL,
+  channel VARCHAR NOT NULL,
+  name VARCHAR NOT NULL,
+  verb VARCHAR NOT NULL,
+  value VARCHAR,
+  changed_by VARCHAR NOT NULL,
+  changed DATETIME NOT NULL
+);
+CREATE INDEX factoid_history_name ON factoid_history (channel, name, id)
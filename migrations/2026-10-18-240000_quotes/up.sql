@@ -0,0 +1,11 @@
+-- Memorable lines, either written out by whoever added them or grabbed from what someone last
+-- said, in which case `nickname` is who said it.
+CREATE TABLE quotes (
+  id INTEGER PRIMARY KEY NOT NULL,
+  channel VARCHAR NOT NULL,
+  nickname VARCHAR,
+  text VARCHAR NOT NULL,
+  added_by VARCHAR NOT NULL,
+  added DATETIME NOT NULL
+);
+CREATE INDEX quotes_channel ON quotes (channel)
@@ -0,0 +1,10 @@
+-- The karma of each thing in each channel, from `thing++` and `thing--`. `thing_key` is the
+-- lowercased name with its whitespace collapsed, and `thing` is how it was last spelled.
+CREATE TABLE karma (
+  channel VARCHAR NOT NULL,
+  thing_key VARCHAR NOT NULL,
+  thing VARCHAR NOT NULL,
+  score INTEGER NOT NULL,
+  updated DATETIME NOT NULL,
+  PRIMARY KEY (channel, thing_key)
+)
@@ -0,0 +1,26 @@
+DROP TABLE casemapping;
+
+CREATE TABLE reminders_unkeyed (
+  id INTEGER PRIMARY KEY NOT NULL,
+  creator VARCHAR NOT NULL,
+  target VARCHAR NOT NULL,
+  channel VARCHAR NOT NULL,
+  message VARCHAR NOT NULL,
+  created DATETIME NOT NULL,
+  due DATETIME NOT NULL
+);
+INSERT INTO reminders_unkeyed SELECT id, creator, target, channel, message, created, due FROM reminders;
+DROP TABLE reminders;
+ALTER TABLE reminders_unkeyed RENAME TO reminders;
+
+CREATE TABLE announcements_unkeyed (
+  id INTEGER PRIMARY KEY NOT NULL,
+  creator VARCHAR NOT NULL,
+  channel VARCHAR NOT NULL,
+  schedule VARCHAR NOT NULL,
+  message VARCHAR NOT NULL,
+  next_run DATETIME NOT NULL
+);
+INSERT INTO announcements_unkeyed SELECT id, creator, channel, schedule, message, next_run FROM announcements;
+DROP TABLE announcements;
+ALTER TABLE announcements_unkeyed RENAME TO announcements
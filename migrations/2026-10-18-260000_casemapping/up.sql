@@ -0,0 +1,14 @@
+-- The casemapping that every key was last folded with, so that they're only recomputed when the
+-- server's differs. The earlier migrations folded them with rfc1459.
+CREATE TABLE casemapping (
+  id INTEGER PRIMARY KEY NOT NULL CHECK (id = 0),
+  mapping VARCHAR NOT NULL
+);
+INSERT INTO casemapping (id, mapping) VALUES (0, 'rfc1459');
+
+ALTER TABLE reminders ADD COLUMN creator_key VARCHAR NOT NULL DEFAULT '';
+UPDATE reminders SET
+  creator_key = lower(replace(replace(replace(replace(creator, '[', '{'), ']', '}'), '\', '|'), '~', '^'));
+ALTER TABLE announcements ADD COLUMN creator_key VARCHAR NOT NULL DEFAULT '';
+UPDATE announcements SET
+  creator_key = lower(replace(replace(replace(replace(creator, '[', '{'), ']', '}'), '\', '|'), '~', '^'))
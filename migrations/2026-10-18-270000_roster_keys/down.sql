@@ -0,0 +1,14 @@
+CREATE TABLE roster_unkeyed (
+  channel VARCHAR NOT NULL,
+  nickname VARCHAR NOT NULL,
+  username VARCHAR,
+  hostname VARCHAR,
+  account VARCHAR,
+  away BOOLEAN NOT NULL DEFAULT 'f',
+  modes VARCHAR NOT NULL DEFAULT '',
+  PRIMARY KEY (channel, nickname)
+);
+INSERT OR REPLACE INTO roster_unkeyed
+  SELECT channel, nickname, username, hostname, account, away, modes FROM roster;
+DROP TABLE roster;
+ALTER TABLE roster_unkeyed RENAME TO roster
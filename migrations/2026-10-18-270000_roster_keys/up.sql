@@ -0,0 +1,18 @@
+-- Rows are keyed by the folded channel and nickname, so that each member can be written on its
+-- own whatever spelling the server used. Keys are folded with rfc1459, like the earlier ones.
+CREATE TABLE roster_keyed (
+  channel VARCHAR NOT NULL,
+  nickname VARCHAR NOT NULL,
+  username VARCHAR,
+  hostname VARCHAR,
+  account VARCHAR,
+  away BOOLEAN NOT NULL DEFAULT 'f',
+  modes VARCHAR NOT NULL DEFAULT '',
+  channel_key VARCHAR NOT NULL,
+  nickname_key VARCHAR NOT NULL,
+  PRIMARY KEY (channel_key, nickname_key)
+);
+INSERT OR REPLACE INTO roster_keyed
+  SELECT channel, nickname, username, hostname, account, away, modes, lower(replace(replace(replace(replace(channel, '[', '{'), ']', '}'), '\', '|'), '~', '^')), lower(replace(replace(replace(replace(nickname, '[', '{'), ']', '}'), '\', '|'), '~', '^')) FROM roster;
+DROP TABLE roster;
+ALTER TABLE roster_keyed RENAME TO roster
@@ -0,0 +1,136 @@
+use std::cell::Cell;
+
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel;
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use irc::client::prelude::*;
+use irc::error::Result;
+use irc::error::IrcError::Custom;
+
+use cron::Cron;
+use models::{Announcement, NewAnnouncement};
+use nick;
+use remind::can_send;
+
+/// Occurrences missed by more than this (e.g. while we were disconnected) are skipped rather
+/// than posted late, since a stale announcement is usually worse than none.
+const MAX_LATENESS_MINUTES: i64 = 5;
+
+/// Recurring announcements, stored in the database along with the time each one next runs. As
+/// with reminders, the earliest of those is cached so that checking for due ones is usually free.
+pub struct Announcements {
+    conn: SqliteConnection,
+    next_due: Cell<Option<NaiveDateTime>>,
+}
+
+impl Announcements {
+    pub fn load(conn: SqliteConnection) -> QueryResult<Announcements> {
+        let announcements = Announcements { conn, next_due: Cell::new(None) };
+        announcements.refresh()?;
+        Ok(announcements)
+    }
+
+    /// Adds an announcement, returning when it will first run, or `None` if the schedule never
+    /// occurs (in which case nothing is added).
+    pub fn add(
+        &self, creator: &str, channel: &str, schedule: &str, cron: &Cron, message: &str,
+    ) -> QueryResult<Option<NaiveDateTime>> {
+        use schema::announcements;
+
+        let next_run = match cron.next_after(Utc::now().naive_utc()) {
+            Some(next_run) => next_run,
+            None => return Ok(None),
+        };
+        diesel::insert_into(announcements::table).values(&NewAnnouncement {
+            creator,
+            channel,
+            schedule,
+            message,
+            next_run: &next_run,
+            creator_key: &nick::fold(creator),
+        }).execute(&self.conn)?;
+        self.refresh()?;
+        Ok(Some(next_run))
+    }
+
+    pub fn all(&self) -> QueryResult<Vec<Announcement>> {
+        use schema::announcements::dsl;
+
+        dsl::announcements.order((dsl::channel, dsl::id)).load(&self.conn)
+    }
+
+    /// Removes an announcement, returning whether there was one to remove.
+    pub fn remove(&self, id: i32) -> QueryResult<bool> {
+        use schema::announcements::dsl;
+
+        let deleted = diesel::delete(dsl::announcements.filter(dsl::id.eq(id)))
+            .execute(&self.conn)?;
+        self.refresh()?;
+        Ok(deleted > 0)
+    }
+
+    /// Posts every announcement that is due and schedules its next occurrence. This is called on
+    /// every tick of the scheduler.
+    pub fn fire(&self, client: &IrcClient) -> Result<()> {
+        use schema::announcements::dsl;
+
+        let now = Utc::now().naive_utc();
+        match self.next_due.get() {
+            Some(next) if next <= now => (),
+            _ => return Ok(()),
+        }
+
+        let due = dsl::announcements
+            .filter(dsl::next_run.le(now))
+            .order(dsl::next_run)
+            .load::<Announcement>(&self.conn)
+            .map_err(|e| Custom { inner: e.into() })?;
+
+        for announcement in &due {
+            if !can_send(client, &announcement.channel) {
+                continue;
+            }
+
+            let late = now.signed_duration_since(announcement.next_run);
+            if late > Duration::minutes(MAX_LATENESS_MINUTES) {
+                info!(
+                    "skipping announcement #{} in {}, missed by {}s",
+                    announcement.id, announcement.channel, late.num_seconds()
+                );
+            } else {
+                client.send_privmsg(&announcement.channel, &announcement.message)?;
+            }
+
+            let next_run = announcement.schedule.parse::<Cron>().ok()
+                .and_then(|cron| cron.next_after(now));
+            let updated = match next_run {
+                Some(next_run) => diesel::update(dsl::announcements.find(announcement.id))
+                    .set(dsl::next_run.eq(next_run))
+                    .execute(&self.conn),
+                None => {
+                    warn!(
+                        "removing announcement #{} with schedule {} that never runs again",
+                        announcement.id, announcement.schedule
+                    );
+                    diesel::delete(dsl::announcements.find(announcement.id)).execute(&self.conn)
+                }
+            };
+            updated.map_err(|e| Custom { inner: e.into() })?;
+        }
+
+        self.refresh().map_err(|e| Custom { inner: e.into() })
+    }
+
+    fn refresh(&self) -> QueryResult<()> {
+        use schema::announcements::dsl;
+
+        let next = dsl::announcements
+            .select(dsl::next_run)
+            .order(dsl::next_run)
+            .first::<NaiveDateTime>(&self.conn)
+            .optional()?;
+        self.next_due.set(next);
+        Ok(())
+    }
+}
@@ -1,3 +1,5 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::rc::Rc;
 use std::time::Duration;
 
@@ -7,16 +9,27 @@ use diesel::sqlite::SqliteConnection;
 use failure::err_msg;
 use irc::client::prelude::*;
 use irc::error::IrcError::Timer;
+use tokio_core::reactor::Handle;
 use tokio_timer::wheel;
 
+use announce::Announcements;
+use backoff::Backoff;
 use cmd::*;
-use dispatch::Dispatcher;
+use config::option;
+use descriptions::Descriptions;
+use dispatch::{Dispatcher, Enable, Help};
 use error::*;
+use factoids::Factoids;
+use nick;
+use people::People;
+use perms::Permissions;
+use remind::Reminders;
+use roster::Roster;
 
 // Embed Diesel migrations.
 embed_migrations!();
 
-pub fn main_impl() -> Result<()> {
+pub fn main_impl(backoff: &Rc<RefCell<Backoff>>) -> Result<()> {
     let clap = App::new("awebot")
         .version(env!("CARGO_PKG_VERSION"))
         .author("Aaron Weiss <awe@pdgn.co>")
@@ -24,49 +37,96 @@ pub fn main_impl() -> Result<()> {
         .arg(Arg::with_name("config").help("Configuration file for awebot").required(true).index(1))
         .get_matches();
 
-    let config = Config::load(
-        clap.value_of("config").unwrap()
-    )?;
-    let db_path = config.get_option("database").ok_or_else(|| {
-        Permanent(err_msg("must specify a database path in the configuration"))
-    })?;
-    let () = embedded_migrations::run(&SqliteConnection::establish(db_path)?).map_err(|e| {
-        Permanent(DatabaseSetupFailed {
-            database: db_path.to_owned(),
-            cause: e,
-        }.into())
-    })?;
-    let whois = Rc::new(Whois::from(SqliteConnection::establish(db_path)?));
-
+    let config_path = clap.value_of("config").unwrap().to_owned();
+    let config = Config::load(&config_path)?;
+    backoff.borrow_mut().configure(&config);
 
     let mut reactor = IrcReactor::new()?;
 
-    let dispatcher = dispatcher!(
-        '@',
-        Rehash::from(config.owners.clone().unwrap_or_else(Vec::new)),
-        Tell::from(SqliteConnection::establish(db_path)?),
-        IAm::from(SqliteConnection::establish(db_path)?),
-        Whoami::from(whois.clone()),
-        whois,
-        SendTweet::new(&config, reactor.inner_handle()),
-    );
+    let db_path = prepare_database(&config)?;
+    let roster = Roster::load(SqliteConnection::establish(db_path)?)?;
+    let state = HandlerState {
+        handle: reactor.inner_handle(),
+        rehash: Rc::new(RefCell::new(None)),
+        last_message: Rc::new(RefCell::new(HashMap::new())),
+        last_said: Rc::new(RefCell::new(HashMap::new())),
+        pending_changes: Rc::new(RefCell::new(HashMap::new())),
+        roster: Rc::new(roster),
+        reminders: Rc::new(Reminders::load(SqliteConnection::establish(db_path)?)?),
+        announcements: Rc::new(Announcements::load(SqliteConnection::establish(db_path)?)?),
+    };
+    let db_path = db_path.to_owned();
+    let reminders = state.reminders.clone();
+    let announcements = state.announcements.clone();
+    let dispatcher = RefCell::new(state.dispatcher(&config)?);
 
     let client = reactor.prepare_client_and_connect(&config)?;
+    // accounts tell people apart from whoever is using their nickname, and each capability is
+    // requested on its own since a server refuses the whole request if it lacks any of them
+    client.send_cap_req(&[Capability::AccountNotify])?;
+    client.send_cap_req(&[Capability::AccountTag])?;
+    client.send_cap_req(&[Capability::ExtendedJoin])?;
     client.identify()?;
 
+    let config = RefCell::new(config);
+    let backoff = backoff.clone();
+    let registered = Rc::new(Cell::new(false));
+    let scheduler_registered = registered.clone();
+
     reactor.register_client_with_handler(client.clone(), move |client, message| {
         trace!("{}", message.to_string().trimmed());
 
+        // a connection only counts once the server has accepted our registration
+        if let Command::Response(Response::RPL_WELCOME, _, _) = message.command {
+            backoff.borrow_mut().connected();
+            registered.set(true);
+        }
+
+        if let Some(mapping) = nick::casemapping_of(&message) {
+            if nick::set_casemapping(mapping) {
+                state.roster.refold();
+            }
+            // the database remembers what it was keyed for, so this is usually a no-op
+            let rekeyed = SqliteConnection::establish(&db_path)
+                .map_err(|e| e.to_string())
+                .and_then(|conn| nick::rekey(&conn, mapping).map_err(|e| e.to_string()));
+            match rekeyed {
+                Ok(true) => info!("rekeyed nicknames for the server's {:?} casemapping", mapping),
+                Ok(false) => (),
+                Err(e) => error!("failed to rekey nicknames: {}", e),
+            }
+        }
+
+        state.roster.update(client, &message);
+
         if let Command::PRIVMSG(ref target, ref msg) = message.command {
             if let Some(source) = message.source_nickname() {
-                dispatcher.dispatch(
-                    client, source, message.response_target().unwrap_or(target), msg
+                dispatcher.borrow().dispatch(
+                    client, &message, source, message.response_target().unwrap_or(target), msg
                 )?;
             } else {
                 warn!("received PRIVMSG without source");
                 warn!("in: {}", message.to_string().trimmed());
             }
+        } else {
+            dispatcher.borrow().dispatch_event(client, &message)?;
         }
+
+        let requested = state.rehash.borrow_mut().take();
+        if let Some(respond_to) = requested {
+            match rehash(client, &config_path, &mut config.borrow_mut(), &state) {
+                Ok((new_dispatcher, summary)) => {
+                    *dispatcher.borrow_mut() = new_dispatcher;
+                    info!("rehashed: {}", summary);
+                    client.send_privmsg(&respond_to, format!("Rehashed: {}", summary))?;
+                }
+                Err(Ephemeral(e)) | Err(Permanent(e)) => {
+                    error!("failed to rehash: {}", e);
+                    client.send_privmsg(&respond_to, format!("Failed to rehash: {}", e))?;
+                }
+            }
+        }
+
         Ok(())
     });
 
@@ -76,6 +136,7 @@ pub fn main_impl() -> Result<()> {
         .build()
         .interval(Duration::from_secs(20));
 
+    let scheduler_client = client.clone();
     reactor.register_future(who_interval.map_err(Timer).for_each(move |()| {
             for chan in client.list_channels().expect("unreachable") {
                 client.send(Command::WHO(Some(chan.to_owned()), None))?;
@@ -84,10 +145,155 @@ pub fn main_impl() -> Result<()> {
         }
     ));
 
+    let scheduler_interval = wheel()
+        .tick_duration(Duration::from_secs(1))
+        .num_slots(256)
+        .build()
+        .interval(Duration::from_secs(1));
+
+    reactor.register_future(scheduler_interval.map_err(Timer).for_each(move |()| {
+            // anything sent before the server accepts our registration would be rejected
+            if !scheduler_registered.get() {
+                return Ok(());
+            }
+            // a failure in one reminder or announcement shouldn't stop the rest from ever firing
+            if let Err(e) = reminders.fire(&scheduler_client) {
+                error!("failed to send reminders: {}", e);
+            }
+            if let Err(e) = announcements.fire(&scheduler_client) {
+                error!("failed to post announcements: {}", e);
+            }
+            Ok(())
+        }
+    ));
+
     reactor.run()?;
     Ok(())
 }
 
+/// Finds the database path in the configuration and brings its schema up to date.
+fn prepare_database(config: &Config) -> Result<&str> {
+    let db_path = config.get_option("database").ok_or_else(|| {
+        Permanent(err_msg("must specify a database path in the configuration"))
+    })?;
+    let () = embedded_migrations::run(&SqliteConnection::establish(db_path)?).map_err(|e| {
+        Permanent(DatabaseSetupFailed {
+            database: db_path.to_owned(),
+            cause: e,
+        }.into())
+    })?;
+    Ok(db_path)
+}
+
+/// The state shared by every dispatcher built over the lifetime of a connection, so that it
+/// survives a rehash.
+struct HandlerState {
+    handle: Handle,
+    rehash: Rc<RefCell<Option<String>>>,
+    last_message: Rc<RefCell<HashMap<String, String>>>,
+    last_said: Rc<RefCell<HashMap<(String, String), String>>>,
+    pending_changes: Rc<RefCell<HashMap<String, (String, Change)>>>,
+    roster: Rc<Roster>,
+    reminders: Rc<Reminders>,
+    announcements: Rc<Announcements>,
+}
+
+impl HandlerState {
+    fn dispatcher(&self, config: &Config) -> Result<Dispatcher> {
+        let db_path = prepare_database(config)?;
+        let people = Rc::new(People::from(SqliteConnection::establish(db_path)?));
+        let descriptions = Rc::new(
+            Descriptions::new(SqliteConnection::establish(db_path)?, people.clone())
+        );
+        let whois = Rc::new(Whois::new(descriptions.clone(), people.clone()));
+        let tell = Rc::new(
+            Tell::new(config, SqliteConnection::establish(db_path)?, people.clone())
+        );
+        let factoids = Rc::new(Factoids::from(SqliteConnection::establish(db_path)?));
+
+        let mut dispatcher = dispatcher!(
+            '@',
+            Rehash::from(self.rehash.clone()),
+            Roles::from(SqliteConnection::establish(db_path)?),
+            // linking comes first, so that mail finds people under a newly linked nickname
+            Link::from(people.clone()),
+            Mail::from(tell.clone()),
+            tell,
+            IAm::new(descriptions, people.clone(), self.pending_changes.clone()),
+            Whoami::from(whois.clone()),
+            whois,
+            Seen::from(SqliteConnection::establish(db_path)?),
+            Learn::from(factoids.clone()),
+            Factoid::from(factoids),
+            Quote::new(SqliteConnection::establish(db_path)?, self.last_said.clone()),
+            Karma::new(SqliteConnection::establish(db_path)?, people),
+            Remind::from(self.reminders.clone()),
+            Schedule::from(self.announcements.clone()),
+            SendTweet::new(config, self.handle.clone(), self.last_message.clone()),
+        );
+        dispatcher.set_permissions(
+            Permissions::new(config, SqliteConnection::establish(db_path)?)
+        );
+        dispatcher.set_roster(self.roster.clone());
+        if let Some(limit) = option(config, "handler_failure_limit") {
+            dispatcher.set_failure_limit(limit);
+        }
+
+        Ok(dispatcher)
+    }
+}
+
+/// Reloads the configuration at `path`, joining and parting channels to match it, and builds a
+/// new dispatcher from it. Returns the dispatcher along with a summary of what changed.
+fn rehash(
+    client: &IrcClient, path: &str, config: &mut Config, state: &HandlerState,
+) -> Result<(Dispatcher, String)> {
+    let new_config = Config::load(path)?;
+    let dispatcher = state.dispatcher(&new_config)?;
+
+    let old_channels = config.channels.clone().unwrap_or_else(Vec::new);
+    let new_channels = new_config.channels.clone().unwrap_or_else(Vec::new);
+    let contains = |channels: &[String], chan: &str| channels.iter().any(|c| nick::eq(c, chan));
+    let joined: Vec<_> = new_channels.iter().filter(|c| !contains(&old_channels, c)).collect();
+    let parted: Vec<_> = old_channels.iter().filter(|c| !contains(&new_channels, c)).collect();
+
+    for chan in &joined {
+        client.send_join(chan)?;
+    }
+    for chan in &parted {
+        client.send_part(chan)?;
+    }
+
+    let mut changes = Vec::new();
+    if !joined.is_empty() {
+        changes.push(format!("joined {}", join_names(&joined)));
+    }
+    if !parted.is_empty() {
+        changes.push(format!("parted {}", join_names(&parted)));
+    }
+    if config.nickname != new_config.nickname {
+        if let Some(ref nickname) = new_config.nickname {
+            client.send(Command::NICK(nickname.to_owned()))?;
+            changes.push(format!("changed nickname to {}", nickname));
+        }
+    }
+    if config.owners != new_config.owners || config.options != new_config.options {
+        changes.push("updated owners, roles, and options".to_owned());
+    }
+    if config.server != new_config.server || config.port != new_config.port ||
+        config.use_ssl != new_config.use_ssl || config.password != new_config.password {
+        changes.push("server settings will apply on the next reconnect".to_owned());
+    }
+    changes.push("rebuilt handlers".to_owned());
+
+    *config = new_config;
+    Ok((dispatcher, changes.join(", ")))
+}
+
+fn join_names(names: &[&String]) -> String {
+    names.iter().map(|s| &s[..]).collect::<Vec<_>>().join(", ")
+}
+
 trait StringTrim {
     fn trimmed(self) -> Self;
 }
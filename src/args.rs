@@ -0,0 +1,320 @@
+use std::collections::{HashMap, HashSet};
+
+/// A single token from a command line, with quotes removed and escapes resolved.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Token {
+    pub value: String,
+    /// The byte offset in the line where the token starts.
+    pub start: usize,
+    /// Whether any part of the token was quoted or escaped.
+    pub literal: bool,
+}
+
+/// Splits a line into tokens on runs of whitespace. Double quotes group words into a single
+/// token, and a backslash escapes a following quote, backslash, or space. Any other backslash is
+/// kept as-is since it is legal in nicknames. An unterminated quote runs to the end of the line.
+pub fn tokenize(line: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = line.char_indices().peekable();
+
+    loop {
+        while chars.peek().map(|&(_, c)| c.is_whitespace()).unwrap_or(false) {
+            chars.next();
+        }
+
+        let start = match chars.peek() {
+            Some(&(idx, _)) => idx,
+            None => break,
+        };
+
+        let mut value = String::new();
+        let mut literal = false;
+        let mut quoted = false;
+
+        while let Some(&(_, c)) = chars.peek() {
+            if c.is_whitespace() && !quoted {
+                break;
+            }
+            chars.next();
+
+            match c {
+                '\\' => match chars.peek() {
+                    Some(&(_, next)) if next == '"' || next == '\\' || next.is_whitespace() => {
+                        literal = true;
+                        value.push(next);
+                        chars.next();
+                    }
+                    _ => value.push(c),
+                },
+                '"' => {
+                    literal = true;
+                    quoted = !quoted;
+                }
+                c => value.push(c),
+            }
+        }
+
+        tokens.push(Token { value, start, literal });
+    }
+
+    tokens
+}
+
+/// A parameter that a command accepts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Param {
+    /// A positional argument that must be present.
+    Required(&'static str),
+    /// A positional argument that may be omitted.
+    Optional(&'static str),
+    /// Every remaining positional argument, each as a separate value.
+    Variadic(&'static str),
+    /// The remainder of the line, verbatim, starting at the next positional argument.
+    Rest(&'static str),
+    /// A boolean `--name` switch.
+    Flag(&'static str),
+    /// An option written as `--name value` or `--name=value`.
+    Named(&'static str),
+}
+
+impl Param {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Param::Required(name) | Param::Optional(name) | Param::Variadic(name) |
+            Param::Rest(name) | Param::Flag(name) | Param::Named(name) => name,
+        }
+    }
+
+    fn is_option(&self) -> bool {
+        match *self {
+            Param::Flag(_) | Param::Named(_) => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Fail, PartialEq)]
+pub enum ArgError {
+    #[fail(display = "missing argument <{}>", _0)]
+    Missing(&'static str),
+    #[fail(display = "missing a value for --{}", _0)]
+    MissingValue(&'static str),
+    #[fail(display = "unknown option --{}", _0)]
+    UnknownOption(String),
+    #[fail(display = "unexpected argument \"{}\"", _0)]
+    Unexpected(String),
+}
+
+/// The arguments to a command, parsed according to a list of `Param`s.
+#[derive(Debug, Default)]
+pub struct Args {
+    values: HashMap<&'static str, String>,
+    lists: HashMap<&'static str, Vec<String>>,
+    flags: HashSet<&'static str>,
+}
+
+impl Args {
+    /// Gets the value of a `Required`, `Optional`, `Rest`, or `Named` parameter.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(|s| &s[..])
+    }
+
+    /// Gets every value of a `Variadic` parameter.
+    pub fn list(&self, name: &str) -> &[String] {
+        self.lists.get(name).map(|v| &v[..]).unwrap_or(&[])
+    }
+
+    /// Checks whether a `Flag` parameter was passed.
+    pub fn flag(&self, name: &str) -> bool {
+        self.flags.contains(name)
+    }
+}
+
+/// Gets the raw text of `line` starting from its `skip`th token, or `None` if it has no more
+/// than `skip` tokens. This is the same text a `Rest` parameter would get at that position.
+pub fn remainder(line: &str, skip: usize) -> Option<String> {
+    let tokens = tokenize(line);
+    if tokens.len() > skip {
+        Some(rest_of(line, &tokens, skip))
+    } else {
+        None
+    }
+}
+
+fn rest_of(line: &str, tokens: &[Token], idx: usize) -> String {
+    // A lone trailing token is unquoted, so that `"a rust person"` works as expected.
+    if idx + 1 == tokens.len() {
+        tokens[idx].value.clone()
+    } else {
+        line[tokens[idx].start..].trim_right().to_owned()
+    }
+}
+
+/// Parses a line of arguments according to `params`. Options are recognized anywhere before the
+/// start of a `Rest` parameter, and a bare `--` ends option parsing.
+pub fn parse(line: &str, params: &[Param]) -> Result<Args, ArgError> {
+    let tokens = tokenize(line);
+    let mut args = Args::default();
+    let mut positional = params.iter().filter(|p| !p.is_option()).peekable();
+    let mut options_done = !params.iter().any(|p| p.is_option());
+
+    let mut idx = 0;
+    while idx < tokens.len() {
+        let token = &tokens[idx];
+        idx += 1;
+
+        if !options_done && !token.literal && token.value.starts_with("--") {
+            let option = &token.value[2..];
+            if option.is_empty() {
+                options_done = true;
+                continue;
+            }
+
+            let (name, inline) = match option.find('=') {
+                Some(eq) => (&option[..eq], Some(&option[eq + 1..])),
+                None => (option, None),
+            };
+
+            match params.iter().find(|p| p.is_option() && p.name() == name) {
+                Some(&Param::Flag(name)) => {
+                    args.flags.insert(name);
+                    continue;
+                }
+                Some(&Param::Named(name)) => {
+                    let value = match inline {
+                        Some(value) => value.to_owned(),
+                        None if idx < tokens.len() => {
+                            idx += 1;
+                            tokens[idx - 1].value.clone()
+                        }
+                        None => return Err(ArgError::MissingValue(name)),
+                    };
+                    args.values.insert(name, value);
+                    continue;
+                }
+                _ => match positional.peek() {
+                    // Unknown options are left alone when they could be part of free text.
+                    Some(&&Param::Rest(_)) | Some(&&Param::Variadic(_)) => (),
+                    _ => return Err(ArgError::UnknownOption(name.to_owned())),
+                },
+            }
+        }
+
+        match positional.peek().cloned() {
+            Some(&Param::Rest(name)) => {
+                args.values.insert(name, rest_of(line, &tokens, idx - 1));
+                positional.next();
+                break;
+            }
+            Some(&Param::Variadic(name)) => {
+                args.lists.entry(name).or_insert_with(Vec::new).push(token.value.clone());
+            }
+            Some(param) => {
+                args.values.insert(param.name(), token.value.clone());
+                positional.next();
+            }
+            None => return Err(ArgError::Unexpected(token.value.clone())),
+        }
+    }
+
+    for param in positional {
+        match *param {
+            Param::Required(name) | Param::Rest(name) => return Err(ArgError::Missing(name)),
+            _ => (),
+        }
+    }
+
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(line: &str) -> Vec<String> {
+        tokenize(line).into_iter().map(|token| token.value).collect()
+    }
+
+    #[test]
+    fn tokenizing() {
+        assert_eq!(values("  alice   bob "), vec!["alice", "bob"]);
+        assert_eq!(
+            values(r#"learn "rust book" is great"#), vec!["learn", "rust book", "is", "great"]
+        );
+        assert_eq!(values(r#"say \"hi\" a\ b"#), vec!["say", "\"hi\"", "a b"]);
+        assert_eq!(values(r"[a\b] c\\d"), vec![r"[a\b]", r"c\d"]);
+        assert_eq!(values(r#"say "unterminated quote"#), vec!["say", "unterminated quote"]);
+        assert_eq!(values(r#""" x"#), vec!["", "x"]);
+        assert!(values("   ").is_empty());
+
+        let tokens = tokenize(r#"a "b c" d"#);
+        assert_eq!(tokens.iter().map(|t| t.start).collect::<Vec<_>>(), vec![0, 2, 8]);
+        assert_eq!(tokens.iter().map(|t| t.literal).collect::<Vec<_>>(), vec![false, true, false]);
+    }
+
+    #[test]
+    fn positional_params() {
+        let params = [Param::Required("nick"), Param::Optional("channel")];
+        let args = parse("alice", &params).unwrap();
+        assert_eq!(args.get("nick"), Some("alice"));
+        assert_eq!(args.get("channel"), None);
+        assert_eq!(parse("", &params).unwrap_err(), ArgError::Missing("nick"));
+        assert_eq!(parse("a b c", &params).unwrap_err(), ArgError::Unexpected("c".to_owned()));
+
+        let args = parse("a b c", &[Param::Variadic("nicks")]).unwrap();
+        assert_eq!(args.list("nicks"), &["a", "b", "c"]);
+        assert!(args.list("others").is_empty());
+    }
+
+    #[test]
+    fn rest_is_verbatim() {
+        let params = [Param::Required("target"), Param::Rest("message")];
+        let args = parse(r#"alice  the "build"  is   fixed  "#, &params).unwrap();
+        assert_eq!(args.get("message"), Some(r#"the "build"  is   fixed"#));
+        // but a lone quoted word is unquoted
+        let args = parse(r#"alice "hi there""#, &params).unwrap();
+        assert_eq!(args.get("message"), Some("hi there"));
+        assert_eq!(parse("alice", &params).unwrap_err(), ArgError::Missing("message"));
+    }
+
+    #[test]
+    fn options() {
+        let params = [
+            Param::Flag("receipt"), Param::Named("in"), Param::Required("target"),
+            Param::Rest("message"),
+        ];
+        let args = parse("--receipt --in 2h alice hi there", &params).unwrap();
+        assert!(args.flag("receipt"));
+        assert_eq!(args.get("in"), Some("2h"));
+        assert_eq!(args.get("target"), Some("alice"));
+        assert_eq!(args.get("message"), Some("hi there"));
+
+        let args = parse("alice --in=2h hi", &params).unwrap();
+        assert!(!args.flag("receipt"));
+        assert_eq!(args.get("in"), Some("2h"));
+
+        // options stop at the rest of the line, at `--`, and at quoted words
+        let args = parse("alice hi --receipt", &params).unwrap();
+        assert!(!args.flag("receipt"));
+        assert_eq!(args.get("message"), Some("hi --receipt"));
+        let args = parse("-- --receipt hi", &params).unwrap();
+        assert_eq!(args.get("target"), Some("--receipt"));
+        let args = parse(r#""--receipt" hi"#, &params).unwrap();
+        assert_eq!(args.get("target"), Some("--receipt"));
+
+        assert_eq!(parse("alice --in", &params).unwrap_err(), ArgError::MissingValue("in"));
+        assert_eq!(
+            parse("--bogus alice hi", &params).unwrap_err(),
+            ArgError::UnknownOption("bogus".to_owned())
+        );
+    }
+
+    #[test]
+    fn remainders() {
+        assert_eq!(
+            remainder("edit 12 the build  is fixed ", 2), Some("the build  is fixed".to_owned())
+        );
+        assert_eq!(remainder(r#"edit 12 "fixed""#, 2), Some("fixed".to_owned()));
+        assert_eq!(remainder("edit 12", 2), None);
+    }
+}
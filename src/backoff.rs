@@ -0,0 +1,116 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use irc::client::prelude::*;
+use rand;
+
+use config::option;
+
+/// Tracks connection failures to decide how long to wait before reconnecting, and when to stop
+/// trying altogether.
+pub struct Backoff {
+    min_delay: Duration,
+    max_delay: Duration,
+    max_failures: usize,
+    window: Duration,
+    delay: Option<Duration>,
+    failures: VecDeque<Instant>,
+    disconnected_at: Option<Instant>,
+}
+
+impl Default for Backoff {
+    fn default() -> Backoff {
+        Backoff {
+            min_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(300),
+            max_failures: 10,
+            window: Duration::from_secs(600),
+            delay: None,
+            failures: VecDeque::new(),
+            disconnected_at: None,
+        }
+    }
+}
+
+impl Backoff {
+    /// Reads the `reconnect_min_delay`, `reconnect_max_delay`, and `reconnect_window` options in
+    /// seconds, and the `reconnect_max_failures` option. Missing or invalid options keep their
+    /// current values.
+    pub fn configure(&mut self, config: &Config) {
+        if let Some(secs) = option(config, "reconnect_min_delay") {
+            self.min_delay = Duration::from_secs(secs);
+        }
+        if let Some(secs) = option(config, "reconnect_max_delay") {
+            self.max_delay = Duration::from_secs(secs);
+        }
+        if let Some(secs) = option(config, "reconnect_window") {
+            self.window = Duration::from_secs(secs);
+        }
+        if let Some(failures) = option(config, "reconnect_max_failures") {
+            self.max_failures = failures;
+        }
+    }
+
+    /// Records that the server welcomed us after registration, resetting the delay.
+    pub fn connected(&mut self) {
+        self.delay = None;
+        if let Some(since) = self.disconnected_at.take() {
+            info!("reconnected after {} disconnected", describe(since.elapsed()));
+        }
+    }
+
+    /// Records a failure, returning how long to wait before reconnecting, or `None` if there
+    /// have been too many failures within the window to keep trying.
+    pub fn failed(&mut self) -> Option<Duration> {
+        let now = Instant::now();
+        if self.disconnected_at.is_none() {
+            self.disconnected_at = Some(now);
+        }
+
+        self.failures.push_back(now);
+        let window = self.window;
+        while self.failures.front().map(|&t| now.duration_since(t) > window).unwrap_or(false) {
+            self.failures.pop_front();
+        }
+        if self.failures.len() > self.max_failures {
+            return None;
+        }
+
+        let delay = match self.delay {
+            Some(delay) => ::std::cmp::min(delay * 2, self.max_delay),
+            None => self.min_delay,
+        };
+        self.delay = Some(delay);
+
+        // Wait somewhere between half and all of the delay, so that many bots don't retry at once.
+        let half = as_millis(delay) / 2;
+        let jitter = (rand::random::<f64>() * half as f64) as u64;
+        Some(Duration::from_millis(half + jitter))
+    }
+
+    /// The number of failures within the window that escalate to a permanent error.
+    pub fn max_failures(&self) -> usize {
+        self.max_failures
+    }
+
+    /// The window within which failures are counted.
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+}
+
+fn as_millis(dur: Duration) -> u64 {
+    dur.as_secs() * 1000 + u64::from(dur.subsec_nanos() / 1_000_000)
+}
+
+/// Formats a duration as a rough human-readable length of time.
+pub fn describe(dur: Duration) -> String {
+    let secs = dur.as_secs();
+    if secs >= 3600 {
+        format!("{}h{}m", secs / 3600, secs % 3600 / 60)
+    } else if secs >= 60 {
+        format!("{}m{}s", secs / 60, secs % 60)
+    } else {
+        format!("{}.{:03}s", secs, dur.subsec_nanos() / 1_000_000)
+    }
+}
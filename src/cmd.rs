@@ -1,8 +1,8 @@
 use std::cell::RefCell;
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::rc::Rc;
 
-use chrono::Utc;
+use chrono::{Duration, NaiveDateTime, Utc};
 use diesel;
 use diesel::prelude::*;
 use diesel::result::{Error as QueryError};
@@ -14,239 +14,2159 @@ use irc::error::Result;
 use irc::error::IrcError::Custom;
 use tokio_core::reactor::Handle;
 
+use announce::Announcements;
+use args::{self, ArgError, Param};
+use config::option;
+use cron::Cron;
+use descriptions::Descriptions;
 use dispatch::{Context, Handler};
+use factoids::{self, Factoids};
+use karma::{self, Scores};
+use models::{FactoidReply, WhoisRevision};
+use nick;
+use people::People;
+use perms::Role;
+use quotes::Quotes;
+use remind::Reminders;
+use roster;
+use when;
 
+/// Requests that the configuration be reloaded once the current message has been handled,
+/// recording where to report the outcome.
 pub struct Rehash {
-    allowed: HashSet<String>,
+    requested: Rc<RefCell<Option<String>>>,
 }
 
-impl From<Vec<String>> for Rehash {
-    fn from(allowed: Vec<String>) -> Rehash {
-        Rehash {
-            allowed: allowed.into_iter().collect(),
+impl From<Rc<RefCell<Option<String>>>> for Rehash {
+    fn from(requested: Rc<RefCell<Option<String>>>) -> Rehash {
+        Rehash { requested }
+    }
+}
+
+impl Handler for Rehash {
+    fn command(&self) -> &'static [&'static str] {
+        &["rehash"]
+    }
+
+    fn usage(&self) -> &'static str {
+        "rehash"
+    }
+
+    fn summary(&self) -> &'static str {
+        "Reloads the configuration file without reconnecting."
+    }
+
+    fn role(&self) -> Role {
+        Role::Owner
+    }
+
+    fn handle<'a>(&self, context: Context<'a>) -> Result<()> {
+        info!("rehash requested by {}", context.sender);
+        *self.requested.borrow_mut() = Some(context.respond_to.to_owned());
+        Ok(())
+    }
+}
+
+pub struct Roles {
+    conn: SqliteConnection,
+}
+
+impl From<SqliteConnection> for Roles {
+    fn from(conn: SqliteConnection) -> Roles {
+        Roles { conn }
+    }
+}
+
+impl Handler for Roles {
+    fn command(&self) -> &'static [&'static str] {
+        &["role", "roles"]
+    }
+
+    fn usage(&self) -> &'static str {
+        "role list | role grant <role> <mask> | role revoke <mask>"
+    }
+
+    fn summary(&self) -> &'static str {
+        "Binds roles to hostmasks like *!*@example.com or NickServ accounts like $a:alice."
+    }
+
+    fn examples(&self) -> &'static [&'static str] {
+        &["role grant trusted $a:alice", "role revoke $a:alice"]
+    }
+
+    fn role(&self) -> Role {
+        Role::Owner
+    }
+
+    fn handle<'a>(&self, context: Context<'a>) -> Result<()> {
+        use models::*;
+        use schema::roles;
+
+        let args = match context.parse(&[
+            Param::Required("action"), Param::Optional("first"), Param::Optional("second"),
+        ]) {
+            Ok(args) => args,
+            Err(e) => return context.reply(format!("{}. Usage: {}", e, self.usage())),
+        };
+
+        match (args.get("action").unwrap_or_default(), args.get("first"), args.get("second")) {
+            ("list", None, None) => {
+                let bindings = roles::table
+                    .order(roles::mask)
+                    .load::<RoleBinding>(&self.conn)
+                    .map_err(|e| Custom { inner: e.into() })?;
+
+                if bindings.is_empty() {
+                    return context.reply("No roles have been granted.");
+                }
+
+                let bindings: Vec<_> = bindings.iter().map(|binding| {
+                    format!("{} ({})", binding.mask, binding.role)
+                }).collect();
+                context.reply(bindings.join(", "))
+            }
+            ("grant", Some(role), Some(mask)) => {
+                let role: Role = match role.parse() {
+                    Ok(role) => role,
+                    Err(e) => return context.reply(e),
+                };
+
+                diesel::replace_into(roles::table)
+                    .values(&NewRoleBinding { mask, role: &role.to_string() })
+                    .execute(&self.conn)
+                    .map_err(|e| Custom { inner: e.into() })?;
+
+                context.reply(format!("{} now has the {} role.", mask, role))
+            }
+            ("revoke", Some(mask), None) => {
+                let deleted = diesel::delete(roles::table.find(mask))
+                    .execute(&self.conn)
+                    .map_err(|e| Custom { inner: e.into() })?;
+
+                if deleted == 0 {
+                    context.reply(format!("{} didn't have a role.", mask))
+                } else {
+                    context.reply(format!("{} no longer has a role.", mask))
+                }
+            }
+            _ => context.reply(format!("Usage: {}", self.usage())),
+        }
+    }
+}
+
+pub struct Link {
+    people: Rc<People>,
+    /// Links waiting to be confirmed, from the (folded) nickname that asked to the one it asked
+    /// for.
+    requests: RefCell<HashMap<String, String>>,
+}
+
+impl From<Rc<People>> for Link {
+    fn from(people: Rc<People>) -> Link {
+        Link { people, requests: RefCell::new(HashMap::new()) }
+    }
+}
+
+impl Link {
+    /// Groups nicknames by services account, which needs no confirmation.
+    fn saw<'a>(&self, context: Context<'a>) -> Result<()> {
+        match context.account() {
+            Some(account) => self.people.saw_account(context.sender, &account)
+                .map_err(|e| Custom { inner: e.into() }),
+            None => Ok(()),
+        }
+    }
+
+    fn list<'a>(&self, context: Context<'a>, nick: &str) -> Result<()> {
+        let nicks = self.people.nicks_of(nick).map_err(|e| Custom { inner: e.into() })?;
+        match (nicks.len(), nick::eq(nick, context.sender)) {
+            (1, true) => context.reply("Your nickname isn't linked to any others."),
+            (1, false) => context.reply(format!("{} isn't linked to any other nicknames.", nick)),
+            (_, true) => context.reply(format!("You're also known as {}.", nicks[1..].join(", "))),
+            (_, false) => context.reply(
+                format!("{} is also known as {}.", nick, nicks[1..].join(", "))
+            ),
+        }
+    }
+}
+
+impl Handler for Link {
+    fn command(&self) -> &'static [&'static str] {
+        &["link"]
+    }
+
+    fn usage(&self) -> &'static str {
+        "link <nickname> | link list [nickname] | link drop"
+    }
+
+    fn summary(&self) -> &'static str {
+        "Links your nickname to another one of yours, so that mail and whois follow you between \
+         them. Both nicknames have to ask, while identified if they've used a NickServ account. \
+         Nicknames using the same NickServ account are linked automatically."
+    }
+
+    fn examples(&self) -> &'static [&'static str] {
+        &["link alice", "link list", "link drop"]
+    }
+
+    fn handle<'a>(&self, context: Context<'a>) -> Result<()> {
+        match (context.args.first().cloned(), context.args.len()) {
+            (Some("list"), 1) => return self.list(context, context.sender),
+            (Some("list"), 2) => return self.list(context, context.args[1]),
+            (Some("drop"), 1) => {
+                self.requests.borrow_mut().remove(&nick::fold(context.sender));
+                return if self.people.unlink(context.sender)
+                    .map_err(|e| Custom { inner: e.into() })? {
+                    context.reply("Your nickname is no longer linked to any others.")
+                } else {
+                    context.reply("Your nickname wasn't linked to any others.")
+                };
+            }
+            (Some(_), 1) => (),
+            _ => return context.reply(format!("Usage: {}", self.usage())),
+        }
+
+        let nick = context.args[0];
+        if nick::eq(nick, context.sender) || nick::eq(nick, context.client.current_nickname()) {
+            return context.reply("You can only link your nickname to another one of yours.");
+        }
+
+        let (mine, theirs) = (
+            self.people.person_of(context.sender).map_err(|e| Custom { inner: e.into() })?,
+            self.people.person_of(nick).map_err(|e| Custom { inner: e.into() })?,
+        );
+        if mine == theirs {
+            return context.reply(format!("You're already linked to {}.", nick));
+        }
+
+        // a nickname that has used a services account can only ask or confirm while identified
+        // to it, so that whoever else is using the nickname can't claim its mail
+        let known = self.people.account_of(context.sender).map_err(|e| Custom { inner: e.into() })?;
+        if let Some(known) = known {
+            match context.account() {
+                Some(ref account) if nick::eq(account, &known) => (),
+                _ => return context.reply(
+                    format!("Please identify with services as {} first.", known)
+                ),
+            }
+        }
+
+        // a link needs both nicknames to ask for it, so nobody can claim someone else's mail
+        let confirmed = self.requests.borrow().get(&nick::fold(nick))
+            .map(|requested| nick::eq(requested, context.sender))
+            .unwrap_or(false);
+        if confirmed {
+            self.requests.borrow_mut().remove(&nick::fold(nick));
+            self.people.link(context.sender, nick).map_err(|e| Custom { inner: e.into() })?;
+            context.reply(format!("You're now linked to {}.", nick))
+        } else {
+            self.requests.borrow_mut().insert(nick::fold(context.sender), nick.to_owned());
+            context.reply(format!(
+                "Okay, now link {} back to {} from that nickname to confirm.", nick, context.sender
+            ))
+        }
+    }
+
+    fn on_each_message<'a>(&self, context: Context<'a>) -> Result<()> {
+        self.saw(context)
+    }
+
+    fn on_join<'a>(&self, context: Context<'a>) -> Result<()> {
+        self.saw(context)
+    }
+
+    fn on_nick<'a>(&self, context: Context<'a>, _: &str) -> Result<()> {
+        self.saw(context)
+    }
+}
+
+pub struct Tell {
+    conn: SqliteConnection,
+    /// How long undelivered messages are kept by default, if at all.
+    expiry: Option<Duration>,
+    /// The most messages a sender may have waiting at once.
+    sender_quota: usize,
+    /// The most messages that may be waiting for any one recipient.
+    target_quota: usize,
+    /// The most messages delivered at once before the rest are held for `@mail`.
+    batch_size: usize,
+    people: Rc<People>,
+}
+
+impl Tell {
+    /// Creates the handler, reading its limits from the `mail_expiry_days`, `mail_sender_quota`,
+    /// `mail_target_quota`, and `mail_batch_size` options.
+    pub fn new(config: &Config, conn: SqliteConnection, people: Rc<People>) -> Tell {
+        let expiry_days: i64 = option(config, "mail_expiry_days").unwrap_or(30);
+        Tell {
+            conn,
+            expiry: if expiry_days > 0 { Some(Duration::days(expiry_days)) } else { None },
+            sender_quota: option(config, "mail_sender_quota").unwrap_or(50),
+            target_quota: option(config, "mail_target_quota").unwrap_or(20),
+            batch_size: option::<usize>(config, "mail_batch_size").unwrap_or(3).max(1),
+            people,
+        }
+    }
+
+    /// Delivers up to a batch of the messages waiting for the sender under any of their
+    /// nicknames, returning how many there were. Private messages are always sent in a query.
+    /// Public ones are sent to `channel` when the recipient is speaking there, or as a notice
+    /// when they have just arrived.
+    fn deliver<'a>(&self, context: Context<'a>, channel: Option<&str>) -> Result<usize> {
+        use models::{Message, NewMessage};
+        use schema::mail::dsl::*;
+
+        let (client, nick) = (context.client, context.sender);
+
+        // scheduled messages wait until their time has come
+        let now = Utc::now().naive_utc();
+        let keys: Vec<_> = self.people.nicks_of(nick)
+            .map_err(|e| Custom { inner: e.into() })?
+            .iter()
+            .map(|other| nick::fold(other))
+            .collect();
+        let (expired, results): (Vec<_>, Vec<_>) = mail
+            .filter(target_key.eq_any(keys))
+            .order(id)
+            .load::<Message>(&self.conn)
+            .map_err(|e| Custom { inner: e.into() })?
+            .into_iter()
+            // address them by the nickname they're using now
+            .map(|msg| Message { target: nick.to_owned(), .. msg })
+            .filter(|msg| msg.deliver_after.map(|time| time <= now).unwrap_or(true))
+            .partition(|msg| msg.is_expired(now));
+        let remaining = results.len().saturating_sub(self.batch_size);
+
+        let mut receipts = Vec::new();
+        for msg in results.iter().take(self.batch_size) {
+            let place = match channel {
+                _ if msg.private => {
+                    client.send_privmsg(nick, format!("{}", msg))?;
+                    "in a private message".to_owned()
+                }
+                Some(channel) if nick::eq(channel, nick) => {
+                    client.send_privmsg(nick, format!("{}", msg))?;
+                    "in a private message".to_owned()
+                }
+                Some(channel) => {
+                    client.send_privmsg(channel, format!("{}", msg))?;
+                    format!("in {}", channel)
+                }
+                None => {
+                    client.send_notice(nick, format!("{}", msg))?;
+                    "in a notice".to_owned()
+                }
+            };
+            if msg.request_receipt && !nick::eq(&msg.sender, nick) {
+                receipts.push((msg, format!("\"{}\" {}", excerpt(&msg.message), place)));
+            }
+        }
+
+        if remaining > 0 {
+            let more = format!(
+                "{}: you have {} more message{}, type {}mail to read them.",
+                nick, remaining, if remaining == 1 { "" } else { "s" }, context.line_start
+            );
+            match channel {
+                Some(channel) => client.send_privmsg(channel, more)?,
+                None => client.send_notice(nick, more)?,
+            }
+        }
+
+        let delivered: Vec<_> = results.iter().take(self.batch_size)
+            .chain(expired.iter())
+            .map(|msg| msg.id)
+            .collect();
+        diesel::delete(
+            mail.filter(id.eq_any(delivered))
+        ).execute(&self.conn).map_err(|e| Custom { inner: e.into() })?;
+
+        // receipts go back through the mail so that the sender gets them wherever they are
+        let nick_key = nick::fold(nick);
+        let receipts: Vec<_> = receipts.iter().map(|&(msg, ref text)| NewMessage {
+            target: &msg.sender,
+            sender: nick,
+            message: text,
+            sent: &now,
+            private: msg.private,
+            deliver_after: None,
+            request_receipt: false,
+            receipt: true,
+            expires: self.expiry.map(|expiry| now + expiry),
+            target_key: &msg.sender_key,
+            sender_key: &nick_key,
+        }).collect();
+        if !receipts.is_empty() {
+            diesel::insert_into(mail)
+                .values(&receipts)
+                .execute(&self.conn)
+                .map_err(|e| Custom { inner: e.into() })?;
+        }
+
+        Ok(results.len())
+    }
+
+    /// Discards every message that expired before it could be delivered.
+    fn purge_expired(&self, now: NaiveDateTime) -> QueryResult<()> {
+        use schema::mail::dsl::*;
+
+        let expired: Vec<_> = mail
+            .select((id, expires))
+            .load::<(i32, Option<NaiveDateTime>)>(&self.conn)?
+            .into_iter()
+            .filter(|&(_, time)| time.map(|time| time <= now).unwrap_or(false))
+            .map(|(key, _)| key)
+            .collect();
+        if !expired.is_empty() {
+            diesel::delete(mail.filter(id.eq_any(expired))).execute(&self.conn)?;
+        }
+        Ok(())
+    }
+
+    /// Counts the messages waiting to be sent by the person `nick` belongs to and for each of
+    /// `recipients`, the latter keyed by folded nickname. Both count every nickname of the person,
+    /// so that spreading messages across linked nicknames doesn't get around a quota.
+    fn pending(
+        &self, nick: &str, recipients: &[String],
+    ) -> QueryResult<(usize, HashMap<String, usize>)> {
+        use schema::mail::dsl::*;
+
+        let keys_of = |whom: &str| -> QueryResult<Vec<String>> {
+            Ok(self.people.nicks_of(whom)?.iter().map(|other| nick::fold(other)).collect())
+        };
+
+        // receipts are sent by us on their behalf, so they don't count against anyone's quota
+        let outgoing = mail
+            .filter(sender_key.eq_any(keys_of(nick)?))
+            .filter(receipt.eq(false))
+            .count()
+            .get_result::<i64>(&self.conn)?;
+
+        let mut counts = HashMap::new();
+        for recipient in recipients {
+            let received = mail
+                .filter(target_key.eq_any(keys_of(recipient)?))
+                .filter(receipt.eq(false))
+                .count()
+                .get_result::<i64>(&self.conn)?;
+            counts.insert(nick::fold(recipient), received as usize);
+        }
+        Ok((outgoing as usize, counts))
+    }
+
+    /// Expands a target like `alice,bob` or `#channel-ops` (everyone holding op in `#channel`)
+    /// into the nicknames to leave mail for, without ourselves or more than one nickname per
+    /// person. When we aren't in `#channel` but are in one actually named `#channel-ops`, it's
+    /// the ops of that instead. The sender is left out of the channels they expand.
+    fn recipients<'a>(&self, context: Context<'a>, target: &str) -> QueryResult<Vec<String>> {
+        let mut recipients: Vec<String> = Vec::new();
+        let mut people = Vec::new();
+        for part in target.split(',').filter(|part| !part.is_empty()) {
+            let nicks = if part.starts_with('#') || part.starts_with('&') {
+                let named = if part.ends_with("-ops") { &part[..part.len() - 4] } else { part };
+                let chan = if context.roster.is_joined(part) && !context.roster.is_joined(named) {
+                    part
+                } else {
+                    named
+                };
+                context.roster.ops(chan).into_iter()
+                    .filter(|other| !nick::eq(other, context.sender))
+                    .collect()
+            } else {
+                vec![part.to_owned()]
+            };
+
+            for recipient in nicks {
+                let person = self.people.person_of(&recipient)?;
+                if nick::eq(&recipient, context.client.current_nickname()) ||
+                    people.contains(&person) {
+                    continue;
+                }
+                recipients.push(recipient);
+                people.push(person);
+            }
+        }
+        Ok(recipients)
+    }
+
+    /// Lists the sender's undelivered messages in a query, since some of them may be private.
+    fn list<'a>(&self, context: Context<'a>) -> Result<()> {
+        use models::{time_ago_str, Message};
+        use schema::mail::dsl::*;
+
+        const MAX_LISTED: usize = 10;
+
+        let now = Utc::now().naive_utc();
+        let results: Vec<_> = mail
+            .filter(sender_key.eq(nick::fold(context.sender)))
+            .filter(receipt.eq(false))
+            .order(id)
+            .load::<Message>(&self.conn)
+            .map_err(|e| Custom { inner: e.into() })?
+            .into_iter()
+            .filter(|msg| !msg.is_expired(now))
+            .collect();
+
+        if results.is_empty() {
+            return context.reply("You don't have any messages waiting to be delivered.");
+        }
+
+        for msg in results.iter().take(MAX_LISTED) {
+            let mut scheduled = String::new();
+            if let Some(time) = msg.deliver_after {
+                scheduled.push_str(&format!(", not before {}", when::describe(time)));
+            }
+            if let Some(time) = msg.expires {
+                scheduled.push_str(&format!(", expires {}", when::describe(time)));
+            }
+            context.client.send_privmsg(context.sender, format!(
+                "#{} to {} ({}{}): {}",
+                msg.id, msg.target, time_ago_str(msg.sent).to_lowercase(), scheduled, msg.message
+            ))?;
+        }
+        if results.len() > MAX_LISTED {
+            context.client.send_privmsg(context.sender, format!(
+                "... and {} more.", results.len() - MAX_LISTED
+            ))?;
+        }
+
+        Ok(())
+    }
+
+    fn cancel<'a>(&self, context: Context<'a>, key: i32) -> Result<()> {
+        use schema::mail::dsl::*;
+
+        let deleted = diesel::delete(
+            mail.filter(id.eq(key))
+                .filter(sender_key.eq(nick::fold(context.sender)))
+                .filter(receipt.eq(false))
+        ).execute(&self.conn).map_err(|e| Custom { inner: e.into() })?;
+
+        if deleted == 0 {
+            context.reply(format!("You don't have a message #{} waiting to be delivered.", key))
+        } else {
+            context.reply(format!("Cancelled message #{}.", key))
+        }
+    }
+
+    fn edit<'a>(&self, context: Context<'a>, key: i32, text: &str) -> Result<()> {
+        use schema::mail::dsl::*;
+
+        let updated = diesel::update(
+            mail.filter(id.eq(key))
+                .filter(sender_key.eq(nick::fold(context.sender)))
+                .filter(receipt.eq(false))
+        ).set(message.eq(text)).execute(&self.conn).map_err(|e| Custom { inner: e.into() })?;
+
+        if updated == 0 {
+            context.reply(format!("You don't have a message #{} waiting to be delivered.", key))
+        } else {
+            context.reply(format!("Updated message #{}.", key))
+        }
+    }
+}
+
+impl Handler for Tell {
+    fn command(&self) -> &'static [&'static str] {
+        &["tell"]
+    }
+
+    fn usage(&self) -> &'static str {
+        "tell [--receipt] [--expires <duration>] <nickname[,nickname...]|#channel-ops> \
+         [in <duration> | at <time>] <message> | tell list | tell cancel <id> | \
+         tell edit <id> <message>"
+    }
+
+    fn summary(&self) -> &'static str {
+        "Leaves a message for someone, delivered the next time they speak, join, or change nick. \
+         Telling #channel-ops leaves it for everyone holding op in #channel. With --receipt, \
+         you'll be told when and where it was read."
+    }
+
+    fn examples(&self) -> &'static [&'static str] {
+        &[
+            "tell alice the build is fixed!",
+            "tell alice,bob,carol lunch is here",
+            "tell #rust-ops the spam is back",
+            "tell --receipt bob did you get my email?",
+            "tell --expires 1d carol the pizza is in the fridge",
+            "tell alice in 2h the meeting is starting",
+            "tell alice at 2026-11-01 09:00 happy birthday!",
+            "tell list",
+            "tell edit 12 the build is broken again",
+        ]
+    }
+
+    fn handle<'a>(&self, context: Context<'a>) -> Result<()> {
+        use models::*;
+        use schema::mail;
+
+        // subcommands only apply when they're well-formed, so people named e.g. list still get mail
+        let key = context.args.get(1).and_then(|arg| arg.parse::<i32>().ok());
+        match (context.args.first().cloned(), key, context.args.len()) {
+            (Some("list"), _, 1) => return self.list(context),
+            (Some("cancel"), Some(key), 2) => return self.cancel(context, key),
+            (Some("edit"), Some(key), len) if len > 2 => {
+                let args = match context.parse(&[
+                    Param::Required("action"), Param::Required("id"), Param::Rest("message"),
+                ]) {
+                    Ok(args) => args,
+                    Err(e) => return context.reply(format!("{}. Usage: {}", e, self.usage())),
+                };
+                return self.edit(context, key, args.get("message").unwrap_or_default());
+            }
+            _ => (),
+        }
+
+        let args = match context.parse(&[
+            Param::Flag("receipt"), Param::Named("expires"), Param::Required("target"),
+            Param::Rest("message"),
+        ]) {
+            Ok(args) => args,
+            Err(e) => return context.reply(format!("{}. Usage: {}", e, self.usage())),
+        };
+
+        let target = args.get("target").unwrap_or_default();
+        if nick::eq(target, context.client.current_nickname()) {
+            return context.client.send_privmsg(context.respond_to, "I'm right here!");
+        }
+        // only a channel's ops can be told anything, and they have to be asked for by name
+        let channel = target.split(',').find(|part| {
+            (part.starts_with('#') || part.starts_with('&')) && !part.ends_with("-ops")
+        });
+        if let Some(channel) = channel {
+            return context.reply(format!(
+                "I can only leave messages for a channel's ops. Try {}-ops.", channel
+            ));
+        }
+        let mut recipients = self.recipients(context, target)
+            .map_err(|e| Custom { inner: e.into() })?;
+        if recipients.is_empty() {
+            return context.reply(format!("I couldn't find anyone to tell in {}.", target));
+        }
+
+        let now = Utc::now().naive_utc();
+        let expiry = match args.get("expires") {
+            Some(expires) => match when::parse_duration(&[expires]) {
+                Some((expiry, _)) => Some(expiry),
+                None => return context.reply(format!(
+                    "I didn't understand how long to keep it: {}. Try e.g. --expires 2d.", expires
+                )),
+            },
+            None => self.expiry,
+        };
+        // a delivery time, written the same way as for remind, can come before the message
+        let mut message = args.get("message").unwrap_or_default().to_owned();
+        let words: Vec<_> = message.split_whitespace().collect();
+        let deliver_after = match when::parse_time(&words, now) {
+            Some((time, used)) => match args::remainder(&message, used) {
+                Some(rest) => {
+                    message = rest;
+                    Some(time)
+                }
+                None => return context.reply(format!(
+                    "What should I tell them then? Usage: {}", self.usage()
+                )),
+            },
+            None => None,
+        };
+        if deliver_after.map(|time| time <= now).unwrap_or(false) {
+            return context.reply("That time has already passed!");
+        }
+
+        self.purge_expired(now).map_err(|e| Custom { inner: e.into() })?;
+        let (sent, received) = self.pending(context.sender, &recipients)
+            .map_err(|e| Custom { inner: e.into() })?;
+        let full: Vec<_> = recipients.iter()
+            .filter(|recipient| {
+                received.get(&nick::fold(recipient)).cloned().unwrap_or(0) >= self.target_quota
+            })
+            .cloned()
+            .collect();
+        recipients.retain(|recipient| !full.contains(recipient));
+        if recipients.is_empty() {
+            return context.reply(format!(
+                "Sorry, the mailbox for {} is full. Try again once they've read some.",
+                full.join(", ")
+            ));
+        }
+        if sent + recipients.len() > self.sender_quota {
+            return context.reply(format!(
+                "Sorry, you can only have {} messages waiting at once, and you have {}. Use \
+                 tell list and tell cancel to make room.", self.sender_quota, sent
+            ));
+        }
+
+        let keys: Vec<_> = recipients.iter().map(|recipient| nick::fold(recipient)).collect();
+        let sender_key = nick::fold(context.sender);
+        let new_messages: Vec<_> = recipients.iter().zip(&keys).map(|(recipient, key)| NewMessage {
+            target: recipient,
+            sender: context.sender,
+            message: &message,
+            sent: &now,
+            // messages should be private if they were sent in queries
+            private: context.respond_to == context.sender,
+            deliver_after,
+            request_receipt: args.flag("receipt"),
+            receipt: false,
+            // the clock for expiry starts once the message could first be delivered
+            expires: expiry.map(|expiry| deliver_after.unwrap_or(now) + expiry),
+            target_key: key,
+            sender_key: &sender_key,
+        }).collect();
+
+        diesel::insert_into(mail::table)
+            .values(&new_messages)
+            .execute(&self.conn)
+            .map_err(|e| Custom { inner: e.into() })?;
+
+        // name everyone when the target was a list or a channel, so the sender knows who it hit
+        let whom = if recipients.len() == 1 && nick::eq(&recipients[0], target) {
+            "them".to_owned()
+        } else {
+            recipients.join(", ")
+        };
+        match deliver_after {
+            Some(time) => context.reply(
+                format!("I'll let {} know after {}!", whom, when::describe(time))
+            )?,
+            None => context.client.send_privmsg(
+                context.respond_to, format!("{}: I'll let {} know!", context.sender, whom)
+            )?,
+        }
+
+        if !full.is_empty() {
+            context.reply(format!(
+                "The mailbox for {} is full, so they won't get it.", full.join(", ")
+            ))?;
+        }
+        Ok(())
+    }
+
+    fn on_each_message<'a>(&self, context: Context<'a>) -> Result<()> {
+        self.deliver(context, Some(context.respond_to)).map(|_| ())
+    }
+
+    fn on_join<'a>(&self, context: Context<'a>) -> Result<()> {
+        if nick::eq(context.sender, context.client.current_nickname()) {
+            return Ok(());
+        }
+        self.deliver(context, None).map(|_| ())
+    }
+
+    fn on_nick<'a>(&self, context: Context<'a>, _: &str) -> Result<()> {
+        self.deliver(context, None).map(|_| ())
+    }
+}
+
+pub struct Mail {
+    tell: Rc<Tell>,
+}
+
+impl From<Rc<Tell>> for Mail {
+    fn from(tell: Rc<Tell>) -> Mail {
+        Mail { tell }
+    }
+}
+
+impl Handler for Mail {
+    fn command(&self) -> &'static [&'static str] {
+        &["mail"]
+    }
+
+    fn usage(&self) -> &'static str {
+        "mail"
+    }
+
+    fn summary(&self) -> &'static str {
+        "Sends you the next few messages left for you with tell, in a query."
+    }
+
+    fn handle<'a>(&self, context: Context<'a>) -> Result<()> {
+        if self.tell.deliver(context, Some(context.sender))? == 0 {
+            context.client.send_privmsg(context.sender, "You don't have any messages waiting.")?;
+        }
+        Ok(())
+    }
+}
+
+/// A change someone asked to make to their own description, which waits until we know which
+/// services account they are using.
+pub enum Change {
+    Describe(String),
+    Revert(Option<i32>),
+    /// Adds or replaces a fact, by name.
+    SetFact(String, String),
+    ForgetFact(String),
+}
+
+pub struct IAm {
+    descriptions: Rc<Descriptions>,
+    people: Rc<People>,
+    /// Changes waiting on a `WHOIS` to tell us the sender's account, by their (folded) nickname,
+    /// along with where to respond. It's shared so that it survives a rehash.
+    pending: Rc<RefCell<HashMap<String, (String, Change)>>>,
+}
+
+impl IAm {
+    pub fn new(
+        descriptions: Rc<Descriptions>, people: Rc<People>,
+        pending: Rc<RefCell<HashMap<String, (String, Change)>>>,
+    ) -> IAm {
+        IAm { descriptions, people, pending }
+    }
+
+    /// Makes a change to the sender's own description, now that we know their account. A
+    /// description that already belongs to another account, or that an owner has locked, can't
+    /// be changed, which keeps someone using another person's nickname from rewriting it.
+    fn apply(
+        &self, client: &IrcClient, sender: &str, respond_to: &str, account: &str, change: Change,
+    ) -> Result<()> {
+        let reply = |msg: String| client.send_privmsg(respond_to, format!("{}: {}", sender, msg));
+
+        // nicknames using the same account are the same person, so their descriptions are too
+        self.people.saw_account(sender, account).map_err(|e| Custom { inner: e.into() })?;
+        if self.descriptions.is_locked(sender).map_err(|e| Custom { inner: e.into() })? {
+            return reply(format!(
+                "Sorry, the description for {} has been locked by an owner.", sender
+            ));
+        }
+        let owner = self.descriptions.owner(sender).map_err(|e| Custom { inner: e.into() })?;
+        if let Some(owner) = owner {
+            if !nick::eq(&owner, account) {
+                warn!("refused to change the description of {} from account {}", sender, account);
+                return reply(format!(
+                    "Sorry, the description for {} belongs to another services account.", sender
+                ));
+            }
+        }
+
+        match change {
+            Change::Describe(description) => {
+                let person = self.people.person_of(sender)
+                    .map_err(|e| Custom { inner: e.into() })?;
+                self.descriptions
+                    .set(&person, sender, Some(&description), sender, Some(account))
+                    .map_err(|e| Custom { inner: e.into() })?;
+                reply("Got it!".to_owned())
+            }
+            Change::Revert(revision) => {
+                let restored = self.descriptions.revert(sender, revision, sender)
+                    .map_err(|e| Custom { inner: e.into() })?;
+                reply(IAm::reverted("your description", revision, restored))
+            }
+            Change::SetFact(name, value) => {
+                let person = self.people.person_of(sender)
+                    .map_err(|e| Custom { inner: e.into() })?;
+                self.descriptions
+                    .set_fact(&person, sender, &name, Some(&value), Some(account))
+                    .map_err(|e| Custom { inner: e.into() })?;
+                reply("Got it!".to_owned())
+            }
+            Change::ForgetFact(name) => {
+                let person = self.people.person_of(sender)
+                    .map_err(|e| Custom { inner: e.into() })?;
+                if self.descriptions.set_fact(&person, sender, &name, None, Some(account))
+                    .map_err(|e| Custom { inner: e.into() })? {
+                    reply(format!("Okay, I've forgotten your {}.", name))
+                } else {
+                    reply(format!("You never told me your {}.", name))
+                }
+            }
+        }
+    }
+
+    /// Describes the outcome of reverting `whose` to `revision`.
+    fn reverted(whose: &str, revision: Option<i32>, restored: Option<WhoisRevision>) -> String {
+        match (restored, revision) {
+            (Some(WhoisRevision { id, description: Some(description), .. }), _) => {
+                format!("Restored {} from #{}: {}", whose, id, description)
+            }
+            (Some(WhoisRevision { id, description: None, .. }), _) => {
+                format!("Removed {}, as it was at #{}.", whose, id)
+            }
+            (None, Some(revision)) => format!("There isn't a revision #{} of {}.", revision, whose),
+            (None, None) => format!("There's nothing earlier to restore {} to.", whose),
+        }
+    }
+}
+
+impl Handler for IAm {
+    fn command(&self) -> &'static [&'static str] {
+        &["iam"]
+    }
+
+    fn usage(&self) -> &'static str {
+        "iam <description> | iam +<fact> <value> | iam -<fact> | \
+         iam revert [nickname] [revision]"
+    }
+
+    fn summary(&self) -> &'static str {
+        "Tells me who you are, or facts about you, so that others can ask with whois. You need to \
+         be identified with services, and only your account can change them afterwards. \
+         Reverting goes back to the previous description, or to a revision listed by \
+         whois --history."
+    }
+
+    fn examples(&self) -> &'static [&'static str] {
+        &[
+            "iam a Rust programmer from Boston",
+            "iam +likes coffee",
+            "iam -likes",
+            "iam revert",
+            "iam revert 12",
+        ]
+    }
+
+    fn handle<'a>(&self, context: Context<'a>) -> Result<()> {
+        let change = if context.args.first() == Some(&"revert") && context.args.len() <= 3 {
+            let parse_revision = |arg: &str| arg.trim_left_matches('#').parse::<i32>().ok();
+            let (nick, revision) = match (context.args.get(1), context.args.get(2)) {
+                (None, _) => (context.sender, None),
+                (Some(arg), None) => match parse_revision(arg) {
+                    Some(revision) => (context.sender, Some(revision)),
+                    None => (*arg, None),
+                },
+                (Some(nick), Some(arg)) => match parse_revision(arg) {
+                    Some(revision) => (*nick, Some(revision)),
+                    None => return context.reply(format!("Usage: {}", self.usage())),
+                },
+            };
+
+            let own = nick::eq(nick, context.sender) ||
+                self.people.person_of(nick).map_err(|e| Custom { inner: e.into() })? ==
+                self.people.person_of(context.sender).map_err(|e| Custom { inner: e.into() })?;
+            if !own {
+                // owners are recognized by their account or hostmask already
+                if context.role()? < Role::Owner {
+                    return context.reply("Only owners can revert someone else's description.");
+                }
+                let restored = self.descriptions.revert(nick, revision, context.sender)
+                    .map_err(|e| Custom { inner: e.into() })?;
+                if let Some(ref restored) = restored {
+                    let detail = format!("#{}", restored.id);
+                    let key = &restored.nickname_key;
+                    self.descriptions.audit(key, nick, "revert", Some(&detail), context.sender)
+                        .map_err(|e| Custom { inner: e.into() })?;
+                }
+                let whose = format!("{}'s description", nick);
+                return context.reply(IAm::reverted(&whose, revision, restored));
+            }
+            Change::Revert(revision)
+        } else if let Some((sign, name)) = context.args.first().and_then(|arg| fact_name(arg)) {
+            match (sign, args::remainder(context.line, 1)) {
+                ('+', Some(value)) => Change::SetFact(name, value),
+                ('-', None) => Change::ForgetFact(name),
+                _ => return context.reply(format!("Usage: {}", self.usage())),
+            }
+        } else {
+            match context.parse(&[Param::Rest("description")]) {
+                Ok(args) => {
+                    Change::Describe(args.get("description").unwrap_or_default().to_owned())
+                }
+                Err(ArgError::Missing(_)) => return context.client.send_privmsg(
+                    context.respond_to, format!(
+                        "{}: Who are you? Let me know by writing a description after the \
+                         command!",
+                        context.sender,
+                    )
+                ),
+                Err(e) => return context.reply(e),
+            }
+        };
+
+        match context.account() {
+            Some(account) => self.apply(
+                context.client, context.sender, context.respond_to, &account, change
+            ),
+            None => {
+                // the change waits until the server tells us whether they're identified
+                self.pending.borrow_mut().insert(
+                    nick::fold(context.sender), (context.respond_to.to_owned(), change)
+                );
+                context.client.send(Command::WHOIS(None, context.sender.to_owned()))
+            }
+        }
+    }
+
+    fn on_event<'a>(&self, context: Context<'a>) -> Result<()> {
+        if let Some((nick, account)) = roster::whois_account(context.message) {
+            let pending = self.pending.borrow_mut().remove(&nick::fold(nick));
+            if let Some((respond_to, change)) = pending {
+                return self.apply(context.client, nick, &respond_to, account, change);
+            }
+        }
+
+        // the end of a reply that didn't name an account means they aren't identified
+        if let Command::Response(Response::RPL_ENDOFWHOIS, ref args, _) = context.message.command {
+            let pending = args.get(1).and_then(|nick| {
+                self.pending.borrow_mut().remove(&nick::fold(nick)).map(|pending| (nick, pending))
+            });
+            if let Some((nick, (respond_to, _))) = pending {
+                return context.client.send_privmsg(respond_to, format!(
+                    "{}: Sorry, you need to be identified with services to change your \
+                     description.", nick
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// How many revisions `whois --history` lists.
+const HISTORY_LENGTH: i64 = 10;
+/// How many matches `whois --search` lists.
+const SEARCH_LENGTH: i64 = 10;
+
+pub struct Whois {
+    descriptions: Rc<Descriptions>,
+    people: Rc<People>,
+}
+
+impl Whois {
+    pub fn new(descriptions: Rc<Descriptions>, people: Rc<People>) -> Whois {
+        Whois { descriptions, people }
+    }
+
+    /// Sends the recent revisions of the description of `nick` in a query.
+    fn history<'a>(&self, context: Context<'a>, nick: &str) -> Result<()> {
+        let revisions = self.descriptions.history(nick, HISTORY_LENGTH)
+            .map_err(|e| Custom { inner: e.into() })?;
+
+        if revisions.is_empty() {
+            return context.reply(format!("{} has never described themselves.", nick));
+        }
+
+        context.client.send_privmsg(
+            context.sender, format!("Recent descriptions of {}, newest first:", nick)
+        )?;
+        for revision in &revisions {
+            context.client.send_privmsg(context.sender, revision.to_string())?;
+        }
+
+        Ok(())
+    }
+
+    /// Handles the commands owners use to clean up someone's description, which are recorded in
+    /// the audit log.
+    fn moderate<'a>(&self, context: Context<'a>) -> Result<()> {
+        let (action, nick) = (context.args[0], context.args[1]);
+        if context.role()? < Role::Owner {
+            warn!("refused whois {} from {}", action, context.sender);
+            return context.reply(
+                format!("Sorry, whois {} requires the {} role.", action, Role::Owner)
+            );
+        }
+
+        match action {
+            "forget" => if self.descriptions.forget(nick, context.sender)
+                .map_err(|e| Custom { inner: e.into() })? {
+                context.reply(format!("Okay, I've forgotten everything {} told me.", nick))
+            } else {
+                context.reply(format!("{} never told me anything.", nick))
+            },
+            "lock" | "unlock" => {
+                let locked = action == "lock";
+                let changed = self.descriptions.lock(nick, locked, context.sender)
+                    .map_err(|e| Custom { inner: e.into() })?;
+                context.reply(match (locked, changed) {
+                    (true, true) => {
+                        format!("Locked {}'s description, so only owners can change it.", nick)
+                    }
+                    (true, false) => format!("{}'s description was already locked.", nick),
+                    (false, true) => format!("Unlocked {}'s description.", nick),
+                    (false, false) => format!("{}'s description wasn't locked.", nick),
+                })
+            }
+            _ => {
+                let description = match args::remainder(context.line, 2) {
+                    Some(description) => description,
+                    None => return context.reply(format!(
+                        "What should {} be described as? Usage: {}", nick, self.usage()
+                    )),
+                };
+                self.descriptions.replace(nick, &description, context.sender)
+                    .map_err(|e| Custom { inner: e.into() })?;
+                context.reply(format!("Okay, {} is {} now.", nick, description))
+            }
+        }
+    }
+
+    /// Lists the people whose descriptions or facts mention every word of `query`, along with
+    /// which of their facts did.
+    fn search<'a>(&self, context: Context<'a>, query: &str) -> Result<()> {
+        if query.trim().is_empty() {
+            return context.reply(format!("What should I search for? Usage: {}", self.usage()));
+        }
+
+        let found = self.descriptions.search(query, SEARCH_LENGTH)
+            .map_err(|e| Custom { inner: e.into() })?;
+        if found.is_empty() {
+            return context.reply(format!("Nobody has told me anything about {}.", query));
+        }
+
+        let mut people: Vec<(String, String, Vec<String>)> = Vec::new();
+        for found in found {
+            match people.iter().position(|&(ref key, _, _)| *key == found.nickname_key) {
+                Some(idx) => if !found.field.is_empty() {
+                    people[idx].2.push(found.field);
+                },
+                None => {
+                    let fields = if found.field.is_empty() { vec![] } else { vec![found.field] };
+                    people.push((found.nickname_key, found.nickname, fields));
+                }
+            }
+        }
+
+        let people: Vec<_> = people.iter().map(|&(_, ref nick, ref fields)| {
+            if fields.is_empty() {
+                nick.to_owned()
+            } else {
+                format!("{} ({})", nick, fields.join(", "))
+            }
+        }).collect();
+        context.reply(format!("Matching {}: {}", query, people.join(", ")))
+    }
+}
+
+impl Handler for Whois {
+    fn command(&self) -> &'static [&'static str] {
+        &["whois", "whodat"]
+    }
+
+    fn usage(&self) -> &'static str {
+        "whois [--history] <nickname>... | whois <nickname> <fact> | whois --search <words> | \
+         whois forget|lock|unlock <nickname> | whois set <nickname> <description>"
+    }
+
+    fn summary(&self) -> &'static str {
+        "Tells you who someone is, as they described themselves with iam, or with --history, \
+         how that has changed. Searching finds everyone whose description or facts mention \
+         something. Owners can forget, lock, or set anyone's description."
+    }
+
+    fn examples(&self) -> &'static [&'static str] {
+        &[
+            "whois alice",
+            "whois alice bob",
+            "whois alice likes",
+            "whois --history alice",
+            "whois --search rust",
+            "whois lock alice",
+        ]
+    }
+
+    fn handle<'a>(&self, context: Context<'a>) -> Result<()> {
+        match (context.args.first().cloned(), context.args.len()) {
+            (Some("forget"), 2) | (Some("lock"), 2) | (Some("unlock"), 2) => {
+                return self.moderate(context)
+            }
+            (Some("set"), len) if len >= 2 => return self.moderate(context),
+            _ => (),
+        }
+
+        let args = match context.parse(&[
+            Param::Flag("history"), Param::Flag("search"), Param::Variadic("nicknames"),
+        ]) {
+            Ok(args) => args,
+            Err(e) => return context.reply(e),
+        };
+
+        if args.flag("search") {
+            return self.search(context, &args.list("nicknames").join(" "));
+        }
+
+        if args.list("nicknames").is_empty() {
+            return context.client.send_privmsg(
+                context.respond_to, format!(
+                    "{}: Who do you want to know about? Let me know by writing their nickname \
+                     after the command!",
+                    context.sender,
+                )
+            );
+        }
+
+        if args.flag("history") {
+            for nick in args.list("nicknames") {
+                self.history(context, nick)?;
+            }
+            return Ok(());
+        }
+
+        // a nickname followed by the name of one of their facts asks for just that fact
+        let nicknames = args.list("nicknames");
+        if nicknames.len() == 2 {
+            let fact = self.descriptions.fact(&nicknames[0], &nicknames[1])
+                .map_err(|e| Custom { inner: e.into() })?;
+            if let Some(fact) = fact {
+                return if nick::eq(&nicknames[0], context.sender) {
+                    context.reply(format!("your {}: {}", fact.name, fact.value))
+                } else {
+                    context.reply(format!("{}'s {}: {}", nicknames[0], fact.name, fact.value))
+                };
+            }
+        }
+
+        let sender = self.people.person_of(context.sender)
+            .map_err(|e| Custom { inner: e.into() })?;
+        for nick in nicknames {
+            if nick.is_empty() { continue }
+
+            let found = self.descriptions.get(nick).map_err(|e| Custom { inner: e.into() })?;
+            let facts: Vec<_> = self.descriptions.facts(nick)
+                .map_err(|e| Custom { inner: e.into() })?
+                .into_iter()
+                .map(|fact| fact.name)
+                .collect();
+            let is_sender = nick::eq(nick, context.sender) ||
+                self.people.person_of(nick).map_err(|e| Custom { inner: e.into() })? == sender;
+            let also = if facts.is_empty() { String::new() } else {
+                format!(" (also: {})", facts.join(", "))
+            };
+            let msg = match found {
+                Some(res) => if is_sender {
+                    format!(
+                        "{}: you are {}{}", context.sender, res.description, also
+                    )
+                } else {
+                    format!(
+                        "{}: {} is {}{}", context.sender, nick, res.description, also
+                    )
+                },
+                None if !facts.is_empty() => if is_sender {
+                    format!(
+                        "{}: I don't know who you are, but I know your {}.",
+                        context.sender, facts.join(", ")
+                    )
+                } else {
+                    format!(
+                        "{}: I don't know who {} is, but I know their {}.",
+                        context.sender, nick, facts.join(", ")
+                    )
+                },
+                None => if is_sender {
+                    format!(
+                        "{}: I don't know who you are. Why don't you tell me about yourself with \
+                         iam?", context.sender
+                    )
+                } else {
+                    format!(
+                        "{}: I don't know who {} is.", context.sender, nick
+                    )
+                },
+            };
+
+            context.client.send_privmsg(context.respond_to, msg)?;
+        }
+
+        Ok(())
+    }
+}
+
+pub struct Whoami {
+    whois: Rc<Whois>,
+}
+
+impl From<Rc<Whois>> for Whoami {
+    fn from(whois: Rc<Whois>) -> Whoami {
+        Whoami { whois }
+    }
+}
+
+impl Handler for Whoami {
+    fn command(&self) -> &'static [&'static str] {
+        &["whoami"]
+    }
+
+    fn usage(&self) -> &'static str {
+        "whoami"
+    }
+
+    fn summary(&self) -> &'static str {
+        "Tells you who you are, as you described yourself with iam."
+    }
+
+    fn handle<'a>(&self, context: Context<'a>) -> Result<()> {
+        self.whois.handle(Context {
+            args: &[context.sender],
+            line: context.sender,
+            .. context
+        })
+    }
+}
+
+pub struct Seen {
+    conn: SqliteConnection,
+}
+
+impl From<SqliteConnection> for Seen {
+    fn from(conn: SqliteConnection) -> Seen {
+        Seen { conn }
+    }
+}
+
+impl Seen {
+    fn record(
+        &self, nickname: &str, action: &str, channel: Option<&str>, detail: Option<&str>,
+    ) -> Result<()> {
+        use models::NewSeenEntry;
+        use schema::seen;
+
+        diesel::replace_into(seen::table)
+            .values(&NewSeenEntry {
+                nickname, action, channel, detail,
+                time: &Utc::now().naive_utc(),
+                nickname_key: &nick::fold(nickname),
+            })
+            .execute(&self.conn)
+            .map_err(|e| Custom { inner: e.into() })?;
+
+        Ok(())
+    }
+}
+
+impl Handler for Seen {
+    fn command(&self) -> &'static [&'static str] {
+        &["seen"]
+    }
+
+    fn usage(&self) -> &'static str {
+        "seen <nickname>"
+    }
+
+    fn summary(&self) -> &'static str {
+        "Tells you when someone was last active, and what they were doing."
+    }
+
+    fn examples(&self) -> &'static [&'static str] {
+        &["seen alice"]
+    }
+
+    fn handle<'a>(&self, context: Context<'a>) -> Result<()> {
+        use models::SeenEntry;
+        use schema::seen;
+
+        let args = match context.parse(&[Param::Required("nickname")]) {
+            Ok(args) => args,
+            Err(e) => return context.reply(format!("{}. Usage: {}", e, self.usage())),
+        };
+
+        let nick = args.get("nickname").unwrap_or_default();
+        if nick::eq(nick, context.client.current_nickname()) {
+            return context.client.send_privmsg(context.respond_to, "I'm right here!");
+        } else if nick::eq(nick, context.sender) {
+            return context.reply("You're right here!");
+        } else if context.roster.is_present(context.respond_to, nick) {
+            return context.reply(format!("{} is right here!", nick));
+        }
+
+        match seen::table.find(&nick::fold(nick)[..]).first::<SeenEntry>(&self.conn) {
+            Ok(entry) => context.reply(entry),
+            Err(QueryError::NotFound) => context.reply(format!("I haven't seen {}.", nick)),
+            Err(e) => Err(Custom { inner: e.into() }),
+        }
+    }
+
+    fn on_each_message<'a>(&self, context: Context<'a>) -> Result<()> {
+        // only messages in channels are recorded, since queries are private
+        if context.respond_to == context.sender {
+            return Ok(());
+        }
+
+        self.record(context.sender, "message", Some(context.respond_to), Some(context.msg))
+    }
+
+    fn on_join<'a>(&self, context: Context<'a>) -> Result<()> {
+        self.record(context.sender, "join", Some(context.respond_to), None)
+    }
+
+    fn on_part<'a>(&self, context: Context<'a>) -> Result<()> {
+        let reason = if context.msg.is_empty() { None } else { Some(context.msg) };
+        self.record(context.sender, "part", Some(context.respond_to), reason)
+    }
+
+    fn on_quit<'a>(&self, context: Context<'a>) -> Result<()> {
+        let reason = if context.msg.is_empty() { None } else { Some(context.msg) };
+        self.record(context.sender, "quit", None, reason)
+    }
+
+    fn on_nick<'a>(&self, context: Context<'a>, old_nick: &str) -> Result<()> {
+        self.record(old_nick, "nick", None, Some(context.sender))?;
+        self.record(context.sender, "renamed", None, Some(old_nick))
+    }
+}
+
+/// How many revisions `factoid history` lists.
+const FACTOID_HISTORY_LENGTH: i64 = 10;
+
+pub struct Learn {
+    factoids: Rc<Factoids>,
+}
+
+impl From<Rc<Factoids>> for Learn {
+    fn from(factoids: Rc<Factoids>) -> Learn {
+        Learn { factoids }
+    }
+}
+
+impl Handler for Learn {
+    fn command(&self) -> &'static [&'static str] {
+        &["learn"]
+    }
+
+    fn usage(&self) -> &'static str {
+        "learn [--global] <name> is <value>"
+    }
+
+    fn summary(&self) -> &'static str {
+        "Teaches me a factoid, which I repeat whenever someone asks for it like rust? A value \
+         starting with <reply> is said as it is, and one starting with <action> is acted out, \
+         with $nick and $channel filled in. Factoids stay in the channel they're learned in, \
+         unless --global is given, which needs the trusted role."
+    }
+
+    fn examples(&self) -> &'static [&'static str] {
+        &[
+            "learn rust is a systems programming language",
+            "learn hello is <reply>Hi, $nick, welcome to $channel!",
+            "learn --global botsnack is <action>munches happily",
+        ]
+    }
+
+    fn handle<'a>(&self, context: Context<'a>) -> Result<()> {
+        let args = match context.parse(&[Param::Flag("global"), Param::Rest("definition")]) {
+            Ok(args) => args,
+            Err(e) => return context.reply(format!("{}. Usage: {}", e, self.usage())),
+        };
+        let (name, verb, value) = match split_definition(args.get("definition").unwrap_or("")) {
+            Some(definition) => definition,
+            None => return context.reply(format!("Usage: {}", self.usage())),
+        };
+
+        let scope = if args.flag("global") {
+            factoids::GLOBAL.to_owned()
+        } else {
+            factoids::scope_of(context.respond_to)
+        };
+        if scope == factoids::GLOBAL && context.role()? < Role::Trusted {
+            return context.reply(format!(
+                "Sorry, global factoids require the {} role. Try teaching me in a channel.",
+                Role::Trusted
+            ));
+        }
+
+        let existing = self.factoids.get(&scope, name).map_err(|e| Custom { inner: e.into() })?;
+        if let Some(existing) = existing {
+            if existing.locked && context.role()? < Role::Owner {
+                return context.reply(format!("Sorry, {} is locked.", existing.name));
+            }
+        }
+
+        self.factoids.learn(&scope, name, verb, value, context.sender)
+            .map_err(|e| Custom { inner: e.into() })?;
+        context.reply(format!("Okay, I'll remember {}.", factoids::normalize(name)))
+    }
+}
+
+pub struct Factoid {
+    factoids: Rc<Factoids>,
+}
+
+impl From<Rc<Factoids>> for Factoid {
+    fn from(factoids: Rc<Factoids>) -> Factoid {
+        Factoid { factoids }
+    }
+}
+
+impl Factoid {
+    /// Sends the recent revisions of a factoid in a query.
+    fn history<'a>(&self, context: Context<'a>, name: &str) -> Result<()> {
+        // the history of the factoid that would be found here, or of the one that would be
+        // learned here if there isn't one
+        let found = self.factoids.find(context.respond_to, name)
+            .map_err(|e| Custom { inner: e.into() })?;
+        let scope = match found {
+            Some(factoid) => factoid.channel,
+            None => factoids::scope_of(context.respond_to),
+        };
+        let revisions = self.factoids.history(&scope, name, FACTOID_HISTORY_LENGTH)
+            .map_err(|e| Custom { inner: e.into() })?;
+
+        if revisions.is_empty() {
+            return context.reply(format!("I've never known anything about {}.", name));
+        }
+
+        context.client.send_privmsg(
+            context.sender, format!("Recent versions of {}, newest first:", name)
+        )?;
+        for revision in &revisions {
+            context.client.send_privmsg(context.sender, revision.to_string())?;
         }
+
+        Ok(())
     }
 }
 
-impl Handler for Rehash {
+impl Handler for Factoid {
     fn command(&self) -> &'static [&'static str] {
-        &["rehash"]
+        &["factoid"]
+    }
+
+    fn usage(&self) -> &'static str {
+        "factoid forget|history|lock|unlock <name>"
+    }
+
+    fn summary(&self) -> &'static str {
+        "Forgets a factoid or shows how it has changed. Owners can lock one so that only they \
+         can change or forget it."
+    }
+
+    fn examples(&self) -> &'static [&'static str] {
+        &["factoid forget rust", "factoid history rust", "factoid lock rules"]
     }
 
     fn handle<'a>(&self, context: Context<'a>) -> Result<()> {
-        if self.allowed.contains(context.sender) {
-            context.client.send_quit(format!("Quitting due to command from {}", context.sender))
+        let (action, name) = match (context.args.first(), args::remainder(context.line, 1)) {
+            (Some(action), Some(name)) => (*action, name),
+            _ => return context.reply(format!("Usage: {}", self.usage())),
+        };
+        if action == "history" {
+            return self.history(context, &name);
+        } else if action != "forget" && action != "lock" && action != "unlock" {
+            return context.reply(format!("Usage: {}", self.usage()));
+        }
+
+        let found = self.factoids.find(context.respond_to, &name)
+            .map_err(|e| Custom { inner: e.into() })?;
+        let factoid = match found {
+            Some(factoid) => factoid,
+            None => return context.reply(format!("I don't know anything about {}.", name)),
+        };
+
+        let role = context.role()?;
+        if action == "forget" {
+            if factoid.locked && role < Role::Owner {
+                return context.reply(format!("Sorry, {} is locked.", factoid.name));
+            }
+            if factoid.channel == factoids::GLOBAL && role < Role::Trusted {
+                return context.reply(format!(
+                    "Sorry, forgetting global factoids requires the {} role.", Role::Trusted
+                ));
+            }
+            self.factoids.forget(&factoid, context.sender)
+                .map_err(|e| Custom { inner: e.into() })?;
+            return context.reply(format!("Okay, I've forgotten {}.", factoid.name));
+        }
+
+        if role < Role::Owner {
+            return context.reply(
+                format!("Sorry, factoid {} requires the {} role.", action, Role::Owner)
+            );
+        }
+        let locked = action == "lock";
+        self.factoids.set_locked(&factoid, locked).map_err(|e| Custom { inner: e.into() })?;
+        if locked {
+            context.reply(format!("Locked {}, so only owners can change it.", factoid.name))
         } else {
-            Ok(())
+            context.reply(format!("Unlocked {}.", factoid.name))
+        }
+    }
+
+    fn on_each_message<'a>(&self, context: Context<'a>) -> Result<()> {
+        let question = context.msg.trim();
+        if !question.ends_with('?') {
+            return Ok(());
+        }
+        let name = question.trim_right_matches('?').trim();
+        if name.is_empty() {
+            return Ok(());
+        }
+
+        let found = self.factoids.find(context.respond_to, name)
+            .map_err(|e| Custom { inner: e.into() })?;
+        match found.map(|factoid| factoid.reply(context.sender, context.respond_to)) {
+            Some(FactoidReply::Say(text)) => context.client.send_privmsg(context.respond_to, text),
+            Some(FactoidReply::Act(text)) => context.client.send_action(context.respond_to, text),
+            None => Ok(()),
         }
     }
 }
 
-pub struct Tell {
-    conn: SqliteConnection,
+/// How many matches `quote search` lists.
+const QUOTE_SEARCH_LENGTH: i64 = 5;
+
+pub struct Quote {
+    quotes: Quotes,
+    /// The last thing each person said in each channel, by the (folded) channel and nickname.
+    /// It's shared so that it survives a rehash.
+    last_said: Rc<RefCell<HashMap<(String, String), String>>>,
 }
 
+impl Quote {
+    pub fn new(
+        conn: SqliteConnection, last_said: Rc<RefCell<HashMap<(String, String), String>>>,
+    ) -> Quote {
+        Quote { quotes: Quotes::from(conn), last_said }
+    }
 
-impl From<SqliteConnection> for Tell {
-    fn from(conn: SqliteConnection) -> Tell {
-        Tell { conn }
+    /// Finds the (folded) channel a quote added here belongs to, which is empty in a query.
+    fn channel_of<'a>(context: &Context<'a>) -> String {
+        if context.respond_to.starts_with('#') || context.respond_to.starts_with('&') {
+            nick::fold(context.respond_to)
+        } else {
+            String::new()
+        }
     }
-}
 
-impl Handler for Tell {
-    fn command(&self) -> &'static [&'static str] {
-        &["tell"]
+    /// Finds the (folded) channel to recall quotes from, replying instead if there isn't one.
+    /// That's this channel, or in a query the one `named`, which the sender has to be in so that
+    /// nobody can read the quotes of a channel they aren't part of.
+    fn scope<'a>(context: Context<'a>, named: Option<&str>) -> Result<Option<String>> {
+        let here = Quote::channel_of(&context);
+        let reply = if !here.is_empty() {
+            match named {
+                Some(named) if !nick::eq(named, context.respond_to) => {
+                    format!("Here, you can only recall quotes from {}.", context.respond_to)
+                }
+                _ => return Ok(Some(here)),
+            }
+        } else {
+            match named {
+                Some(named) if named.starts_with('#') || named.starts_with('&') => {
+                    if context.roster.is_present(named, context.sender) {
+                        return Ok(Some(nick::fold(named)));
+                    }
+                    format!("You can only recall quotes from {} while you're in it.", named)
+                }
+                _ => format!(
+                    "Which channel's quotes? Name it first, e.g. {}quote random #rust.",
+                    context.line_start
+                ),
+            }
+        };
+        context.reply(reply)?;
+        Ok(None)
     }
 
-    fn handle<'a>(&self, context: Context<'a>) -> Result<()> {
-        use models::*;
-        use schema::mail;
+    /// Recalls the quote numbered `id`, as if there were no such quote when it belongs to a
+    /// channel other than this one, or in a query to one the sender isn't in.
+    fn recall<'a>(&self, context: Context<'a>, id: i32) -> Result<()> {
+        let quote = match self.quotes.get(id).map_err(|e| Custom { inner: e.into() })? {
+            Some(quote) => quote,
+            None => return context.reply(format!("There isn't a quote #{}.", id)),
+        };
 
-        if context.args.len() < 2 {
-            return Ok(());
+        let here = Quote::channel_of(&context);
+        let visible = if !here.is_empty() {
+            quote.channel == here
+        } else {
+            quote.channel.is_empty() || context.roster.is_present(&quote.channel, context.sender)
+        };
+        if visible {
+            context.reply(quote)
+        } else {
+            context.reply(format!("There isn't a quote #{}.", id))
         }
+    }
 
-        let target = context.args[0];
-        if target == context.client.current_nickname() {
-            return context.client.send_privmsg(context.respond_to, "I'm right here!");
+    fn grab<'a>(&self, context: Context<'a>, nick: &str) -> Result<()> {
+        let channel = Quote::channel_of(&context);
+        if channel.is_empty() {
+            return context.reply("You can only grab quotes in a channel.");
+        } else if nick::eq(nick, context.sender) {
+            return context.reply("You can't grab your own quote.");
         }
 
-        let new_message = NewMessage {
-            target,
-            sender: context.sender,
-            message: &context.args[1..].join(" "),
-            sent: &Utc::now().naive_utc(),
-            // messages should be private if they were sent in queries
-            private: context.respond_to == context.sender,
+        let said = self.last_said.borrow().get(&(channel.clone(), nick::fold(nick))).cloned();
+        let text = match said {
+            Some(text) => text,
+            None => return context.reply(format!("I haven't heard {} say anything here.", nick)),
         };
 
-        diesel::insert_into(mail::table)
-            .values(&new_message)
-            .execute(&self.conn)
+        // the spelling they're using now, rather than however it was typed
+        let nick = context.roster.user(nick).map(|user| user.nickname)
+            .unwrap_or_else(|| nick.to_owned());
+        let id = self.quotes.add(&channel, Some(&nick), &text, context.sender)
             .map_err(|e| Custom { inner: e.into() })?;
+        context.reply(format!("Grabbed quote #{}.", id))
+    }
 
-        context.client.send_privmsg(
-            context.respond_to, format!("{}: I'll let them know!", context.sender)
-        )?;
+    fn search<'a>(&self, context: Context<'a>, channel: &str, text: &str) -> Result<()> {
+        let found = self.quotes.search(channel, text, QUOTE_SEARCH_LENGTH)
+            .map_err(|e| Custom { inner: e.into() })?;
 
-        Ok(())
+        let (first, rest) = match found.split_first() {
+            Some(found) => found,
+            None => return context.reply(format!("No quotes mention {}.", text)),
+        };
+        if rest.is_empty() {
+            context.reply(first)
+        } else {
+            let others: Vec<_> = rest.iter().map(|quote| format!("#{}", quote.id)).collect();
+            context.reply(format!("{} Also: {}", first, others.join(", ")))
+        }
     }
 
-    fn on_each_message<'a>(&self, context: Context<'a>) -> Result<()> {
-        use models::Message;
-        use schema::mail::dsl::*;
+    fn remove<'a>(&self, context: Context<'a>, id: i32) -> Result<()> {
+        let quote = match self.quotes.get(id).map_err(|e| Custom { inner: e.into() })? {
+            Some(quote) => quote,
+            None => return context.reply(format!("There isn't a quote #{}.", id)),
+        };
+        if !nick::eq(&quote.added_by, context.sender) && context.role()? < Role::Owner {
+            return context.reply("Only owners can remove quotes that someone else added.");
+        }
 
-        let results = mail
-            .filter(target.eq(context.sender))
-            .load::<Message>(&self.conn)
-            .map_err(|e| Custom { inner: e.into() })?;
+        self.quotes.remove(id).map_err(|e| Custom { inner: e.into() })?;
+        context.reply(format!("Removed quote #{}.", id))
+    }
+}
 
-        for msg in results {
-            if msg.private {
-                context.client.send_privmsg(context.sender, format!("{}", msg))?;
-            } else {
-                context.client.send_privmsg(context.respond_to, format!("{}", msg))?;
+impl Handler for Quote {
+    fn command(&self) -> &'static [&'static str] {
+        &["quote", "quotes"]
+    }
+
+    fn usage(&self) -> &'static str {
+        "quote [random [#channel]] | quote <number> | quote add <text> | \
+         quote grab <nickname> | quote search [#channel] <text> | quote remove <number>"
+    }
+
+    fn summary(&self) -> &'static str {
+        "Saves memorable lines, either written out or grabbed from the last thing someone said \
+         here, and recalls them by number, by search, or at random from this channel. In a \
+         query, name the channel to search or pick from."
+    }
+
+    fn examples(&self) -> &'static [&'static str] {
+        &[
+            "quote add <alice> it compiled on the first try",
+            "quote grab alice",
+            "quote 42",
+            "quote search compiled",
+            "quote random",
+        ]
+    }
+
+    fn handle<'a>(&self, context: Context<'a>) -> Result<()> {
+        let parse_id = |arg: &str| arg.trim_left_matches('#').parse::<i32>().ok();
+        match (context.args.first().cloned(), context.args.len()) {
+            (None, _) | (Some("random"), 1) | (Some("random"), 2) => {
+                let channel = match Quote::scope(context, context.args.get(1).cloned())? {
+                    Some(channel) => channel,
+                    None => return Ok(()),
+                };
+                match self.quotes.random(&channel).map_err(|e| Custom { inner: e.into() })? {
+                    Some(quote) => context.reply(quote),
+                    None => context.reply("There aren't any quotes yet."),
+                }
+            }
+            (Some("add"), len) if len >= 2 => {
+                let text = args::remainder(context.line, 1).unwrap_or_default();
+                let id = self.quotes.add(&Quote::channel_of(&context), None, &text, context.sender)
+                    .map_err(|e| Custom { inner: e.into() })?;
+                context.reply(format!("Added quote #{}.", id))
             }
+            (Some("grab"), 2) => self.grab(context, context.args[1]),
+            (Some("search"), len) if len >= 2 => {
+                // in a query, the channel comes before the text
+                let named = if Quote::channel_of(&context).is_empty() {
+                    Some(context.args[1])
+                } else {
+                    None
+                };
+                let channel = match Quote::scope(context, named)? {
+                    Some(channel) => channel,
+                    None => return Ok(()),
+                };
+                let skip = if named.is_some() { 2 } else { 1 };
+                match args::remainder(context.line, skip) {
+                    Some(text) => self.search(context, &channel, &text),
+                    None => context.reply(format!("Usage: {}", self.usage())),
+                }
+            }
+            (Some("remove"), 2) => match parse_id(context.args[1]) {
+                Some(id) => self.remove(context, id),
+                None => context.reply(format!("Usage: {}", self.usage())),
+            },
+            (Some(arg), 1) => match parse_id(arg) {
+                Some(id) => self.recall(context, id),
+                None => context.reply(format!("Usage: {}", self.usage())),
+            },
+            _ => context.reply(format!("Usage: {}", self.usage())),
         }
+    }
 
-        diesel::delete(
-            mail.filter(target.eq(context.sender))
-        ).execute(&self.conn).map_err(|e| Custom { inner: e.into() })?;
-
+    fn on_each_message<'a>(&self, context: Context<'a>) -> Result<()> {
+        let channel = Quote::channel_of(&context);
+        if !channel.is_empty() {
+            self.last_said.borrow_mut().insert(
+                (channel, nick::fold(context.sender)),
+                context.msg.to_owned(),
+            );
+        }
         Ok(())
     }
 }
 
-pub struct IAm {
-    conn: SqliteConnection,
+/// How many things `karma top` and `karma bottom` list.
+const KARMA_RANKING_LENGTH: i64 = 5;
+/// How many votes anyone can cast within `KARMA_WINDOW_MINUTES`, which is also how long they
+/// have to wait before voting on the same thing again.
+const KARMA_VOTES: usize = 5;
+const KARMA_WINDOW_MINUTES: i64 = 10;
+
+pub struct Karma {
+    scores: Scores,
+    people: Rc<People>,
+    /// The recent votes of each person, as the (folded) channel, the thing, and when.
+    recent: RefCell<HashMap<String, Vec<(String, String, NaiveDateTime)>>>,
 }
 
-impl From<SqliteConnection> for IAm {
-    fn from(conn: SqliteConnection) -> IAm {
-        IAm { conn }
+impl Karma {
+    pub fn new(conn: SqliteConnection, people: Rc<People>) -> Karma {
+        Karma { scores: Scores::from(conn), people, recent: RefCell::new(HashMap::new()) }
+    }
+
+    /// Checks whether the person `voter` can vote on `thing` in `channel` now, counting the vote
+    /// if so.
+    fn allow(&self, voter: &str, channel: &str, thing: &str) -> bool {
+        let now = Utc::now().naive_utc();
+        let mut recent = self.recent.borrow_mut();
+        let votes = recent.entry(voter.to_owned()).or_insert_with(Vec::new);
+        votes.retain(|&(_, _, when)| {
+            now.signed_duration_since(when) < Duration::minutes(KARMA_WINDOW_MINUTES)
+        });
+
+        let again = votes.iter().any(|&(ref c, ref t, _)| c == channel && t == thing);
+        if again || votes.len() >= KARMA_VOTES {
+            return false;
+        }
+        votes.push((channel.to_owned(), thing.to_owned(), now));
+        true
     }
 }
 
-impl Handler for IAm {
+impl Handler for Karma {
     fn command(&self) -> &'static [&'static str] {
-        &["iam"]
+        &["karma"]
     }
 
-    fn handle<'a>(&self, context: Context<'a>) -> Result<()> {
-        use models::*;
-        use schema::whois;
+    fn usage(&self) -> &'static str {
+        "karma <thing> | karma top | karma bottom"
+    }
 
-        if context.args.is_empty() {
-            return context.client.send_privmsg(
-                context.respond_to, format!(
-                    "{}: Who are you? Let me know by writing a description after the command!",
-                    context.sender,
-                )
-            );
+    fn summary(&self) -> &'static str {
+        "Shows the karma of something in this channel, or what has the most or least of it. \
+         Anyone can give karma with thing++ or (several words)++ and take it away with thing--, \
+         just not to or from themselves."
+    }
+
+    fn examples(&self) -> &'static [&'static str] {
+        &["karma rust", "karma top", "karma bottom"]
+    }
+
+    fn handle<'a>(&self, context: Context<'a>) -> Result<()> {
+        if !context.respond_to.starts_with('#') && !context.respond_to.starts_with('&') {
+            return context.reply("Karma is kept for each channel, so ask in one.");
         }
+        let channel = nick::fold(context.respond_to);
 
-        let description = context.args.join(" ");
+        match (context.args.first().cloned(), context.args.len()) {
+            (None, _) => context.reply(format!("Usage: {}", self.usage())),
+            (Some(order), 1) if order == "top" || order == "bottom" => {
+                let best = order == "top";
+                let ranked = self.scores.ranked(&channel, best, KARMA_RANKING_LENGTH)
+                    .map_err(|e| Custom { inner: e.into() })?;
+                if ranked.is_empty() {
+                    return context.reply("Nothing here has any karma yet.");
+                }
 
-        let new_whois = NewWhoisEntry {
-            nickname: context.sender,
-            description: &description,
-        };
+                let things: Vec<_> = ranked.iter().map(|entry| entry.to_string()).collect();
+                let heading = if best { "Most karma" } else { "Least karma" };
+                context.reply(format!("{}: {}", heading, things.join(", ")))
+            }
+            _ => {
+                // the parentheses around several words are optional here
+                let thing = context.line.trim().trim_left_matches('(').trim_right_matches(')');
+                let found = self.scores.get(&channel, thing)
+                    .map_err(|e| Custom { inner: e.into() })?;
+                match found {
+                    Some(entry) => {
+                        context.reply(format!("{} has {} karma.", entry.thing, entry.score))
+                    }
+                    None => context.reply(format!("{} has no karma.", thing)),
+                }
+            }
+        }
+    }
 
-        diesel::replace_into(whois::table)
-            .values(&new_whois)
-            .execute(&self.conn)
-            .map_err(|e| Custom { inner: e.into() })?;
+    fn on_each_message<'a>(&self, context: Context<'a>) -> Result<()> {
+        if !context.respond_to.starts_with('#') && !context.respond_to.starts_with('&') {
+            return Ok(());
+        }
+        let votes = karma::votes(context.msg);
+        if votes.is_empty() {
+            return Ok(());
+        }
 
-        context.client.send_privmsg(
-            context.respond_to, format!("{}: Got it!", context.sender)
-        )?;
+        let channel = nick::fold(context.respond_to);
+        let voter = self.people.person_of(context.sender)
+            .map_err(|e| Custom { inner: e.into() })?;
+        let mut limited = false;
+        for (thing, delta) in votes {
+            // nicknames are checked by who they belong to, so alice_++ doesn't work for alice
+            if self.people.person_of(thing).map_err(|e| Custom { inner: e.into() })? == voter {
+                context.reply("You can't change your own karma.")?;
+            } else if self.allow(&voter, &channel, &karma::normalize(thing)) {
+                self.scores.add(&channel, thing, delta).map_err(|e| Custom { inner: e.into() })?;
+            } else {
+                limited = true;
+            }
+        }
 
+        if limited {
+            context.client.send_notice(
+                context.sender, "Slow down, some of your karma votes weren't counted."
+            )?;
+        }
         Ok(())
     }
 }
 
-pub struct Whois {
-    conn: SqliteConnection,
+pub struct Remind {
+    reminders: Rc<Reminders>,
 }
 
-impl From<SqliteConnection> for Whois {
-    fn from(conn: SqliteConnection) -> Whois {
-        Whois { conn }
+impl From<Rc<Reminders>> for Remind {
+    fn from(reminders: Rc<Reminders>) -> Remind {
+        Remind { reminders }
     }
 }
 
-impl Handler for Whois {
+impl Remind {
+    fn list<'a>(&self, context: Context<'a>) -> Result<()> {
+        let pending = self.reminders.created_by(context.sender)
+            .map_err(|e| Custom { inner: e.into() })?;
+
+        if pending.is_empty() {
+            return context.reply("You don't have any pending reminders.");
+        }
+
+        for reminder in &pending {
+            context.client.send_privmsg(context.sender, format!(
+                "#{} for {} at {}: {}",
+                reminder.id, reminder.target, when::describe(reminder.due), reminder.message
+            ))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Handler for Remind {
     fn command(&self) -> &'static [&'static str] {
-        &["whois", "whodat"]
+        &["remind"]
     }
 
-    fn handle<'a>(&self, context: Context<'a>) -> Result<()> {
-        use models::*;
-        use schema::whois::dsl::*;
+    fn usage(&self) -> &'static str {
+        "remind <me|nickname|#channel> <in <duration> | at <time>> [to] <message> | remind list | \
+         remind cancel <id>"
+    }
 
-        if context.args.is_empty() {
-            return context.client.send_privmsg(
-                context.respond_to, format!(
-                    "{}: Who do you want to know about? Let me know by writing their nickname \
-                     after the command!",
-                    context.sender,
-                )
-            );
-        }
+    fn summary(&self) -> &'static str {
+        "Reminds you, someone else, or a channel about something later. Times are in UTC."
+    }
 
-        for nick in context.args {
-            if nick.is_empty() { continue }
+    fn examples(&self) -> &'static [&'static str] {
+        &[
+            "remind me in 20m to check the build",
+            "remind #rust at 17:00 standup",
+            "remind alice at 9am to review my PR",
+            "remind cancel 4",
+        ]
+    }
 
-            let msg = match whois.find(nick).first::<WhoisEntry>(&self.conn) {
-                Ok(res) => if res.nickname == context.sender {
-                    format!(
-                        "{}: you are {}", context.sender, res.description
-                    )
-                } else {
-                    format!(
-                        "{}: {} is {}", context.sender, res.nickname, res.description
-                    )
-                },
-                Err(QueryError::NotFound) => if *nick == context.sender {
-                    format!(
-                        "{}: I don't know who you are. Why don't you tell me about yourself with \
-                         iam?", context.sender
-                    )
+    fn handle<'a>(&self, context: Context<'a>) -> Result<()> {
+        use models::NewReminder;
+
+        match (context.args.first().cloned(), context.args.len()) {
+            (Some("list"), 1) => return self.list(context),
+            (Some("cancel"), 2) => return match context.args[1].parse() {
+                Ok(id) => if self.reminders.cancel(context.sender, id)
+                    .map_err(|e| Custom { inner: e.into() })? {
+                    context.reply(format!("Cancelled reminder #{}.", id))
                 } else {
-                    format!(
-                        "{}: I don't know who {} is.", context.sender, nick
-                    )
+                    context.reply(format!("You don't have a pending reminder #{}.", id))
                 },
-                Err(e) => return Err(Custom { inner: e.into() }),
-            };
+                Err(_) => context.reply(format!("Usage: {}", self.usage())),
+            },
+            (None, _) => return context.reply(format!("Usage: {}", self.usage())),
+            _ => (),
+        }
 
-            context.client.send_privmsg(context.respond_to, msg)?;
+        let now = Utc::now().naive_utc();
+        let (due, used) = match when::parse_time(&context.args[1..], now) {
+            Some(parsed) => parsed,
+            None => return context.reply(format!(
+                "I didn't understand when to remind them. Usage: {}", self.usage()
+            )),
+        };
+        if due <= now {
+            return context.reply("That time has already passed!");
         }
 
-        Ok(())
+        let mut skip = used + 1;
+        if context.args.get(skip).map(|word| *word == "to").unwrap_or(false) {
+            skip += 1;
+        }
+        let message = match args::remainder(context.line, skip) {
+            Some(message) => message,
+            None => return context.reply(format!(
+                "What should I remind them about? Usage: {}", self.usage()
+            )),
+        };
+
+        let target = match context.args[0] {
+            "me" => context.sender,
+            chan if chan.starts_with('#') || chan.starts_with('&') => {
+                if !context.roster.is_present(chan, context.sender) {
+                    return context.reply(format!("You need to be in {} to remind it.", chan));
+                }
+                chan
+            }
+            nick => nick,
+        };
+
+        self.reminders.add(&NewReminder {
+            creator: context.sender,
+            target,
+            channel: context.respond_to,
+            message: &message,
+            created: &now,
+            due: &due,
+            creator_key: &nick::fold(context.sender),
+        }).map_err(|e| Custom { inner: e.into() })?;
+
+        context.reply(format!("Okay, I'll remind {} at {}.", if nick::eq(target, context.sender) {
+            "you"
+        } else {
+            target
+        }, when::describe(due)))
     }
 }
 
-pub struct Whoami {
-    whois: Rc<Whois>,
+pub struct Schedule {
+    announcements: Rc<Announcements>,
 }
 
-impl From<Rc<Whois>> for Whoami {
-    fn from(whois: Rc<Whois>) -> Whoami {
-        Whoami { whois }
+impl From<Rc<Announcements>> for Schedule {
+    fn from(announcements: Rc<Announcements>) -> Schedule {
+        Schedule { announcements }
     }
 }
 
-impl Handler for Whoami {
+impl Schedule {
+    fn add<'a>(&self, context: Context<'a>) -> Result<()> {
+        let channel = match context.args.get(1) {
+            Some(chan) if chan.starts_with('#') || chan.starts_with('&') => *chan,
+            _ => return context.reply(format!("Usage: {}", self.usage())),
+        };
+
+        // the schedule is either a single (quoted or shorthand) word, or five bare fields
+        let (schedule, cron, used) = match context.args.get(2).map(|word| word.parse::<Cron>()) {
+            Some(Ok(cron)) => (context.args[2].to_owned(), cron, 1),
+            _ if context.args.len() >= 7 => {
+                let schedule = context.args[2..7].join(" ");
+                match schedule.parse::<Cron>() {
+                    Ok(cron) => (schedule, cron, 5),
+                    Err(e) => return context.reply(e),
+                }
+            }
+            _ => return context.reply(format!("Usage: {}", self.usage())),
+        };
+
+        let message = match args::remainder(context.line, 2 + used) {
+            Some(message) => message,
+            None => return context.reply(format!(
+                "What should I announce? Usage: {}", self.usage()
+            )),
+        };
+
+        let next_run = self.announcements
+            .add(context.sender, channel, &schedule, &cron, &message)
+            .map_err(|e| Custom { inner: e.into() })?;
+        match next_run {
+            Some(next_run) => context.reply(format!(
+                "Okay, I'll post that in {} on the schedule {}, starting at {}.",
+                channel, schedule, when::describe(next_run)
+            )),
+            None => context.reply(format!("The schedule {} never comes around.", schedule)),
+        }
+    }
+
+    fn list<'a>(&self, context: Context<'a>) -> Result<()> {
+        let announcements = self.announcements.all().map_err(|e| Custom { inner: e.into() })?;
+
+        if announcements.is_empty() {
+            return context.reply("There aren't any scheduled announcements.");
+        }
+
+        for announcement in &announcements {
+            context.client.send_privmsg(context.sender, format!(
+                "#{} in {} ({}, next at {}): {}",
+                announcement.id, announcement.channel, announcement.schedule,
+                when::describe(announcement.next_run), announcement.message
+            ))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Handler for Schedule {
     fn command(&self) -> &'static [&'static str] {
-        &["whoami"]
+        &["schedule"]
+    }
+
+    fn usage(&self) -> &'static str {
+        "schedule add <#channel> <cron expression> <message> | schedule list | \
+         schedule remove <id>"
+    }
+
+    fn summary(&self) -> &'static str {
+        "Posts a message in a channel on a recurring schedule, written as a five-field cron \
+         expression (minute hour day month weekday) in UTC."
+    }
+
+    fn examples(&self) -> &'static [&'static str] {
+        &[
+            "schedule add #team 0 10 * * mon-fri standup: https://meet.example.com/standup",
+            "schedule add #rust \"30 17 * * fri\" Have a good weekend!",
+            "schedule add #rust @monthly New month, new release notes.",
+            "schedule remove 3",
+        ]
+    }
+
+    fn role(&self) -> Role {
+        Role::Owner
     }
 
     fn handle<'a>(&self, context: Context<'a>) -> Result<()> {
-        self.whois.handle(Context {
-            args: &[context.sender],
-            .. context
-        })
+        match (context.args.first().cloned(), context.args.len()) {
+            (Some("add"), _) => self.add(context),
+            (Some("list"), 1) => self.list(context),
+            (Some("remove"), 2) => match context.args[1].parse() {
+                Ok(id) => if self.announcements.remove(id)
+                    .map_err(|e| Custom { inner: e.into() })? {
+                    context.reply(format!("Removed announcement #{}.", id))
+                } else {
+                    context.reply(format!("There isn't an announcement #{}.", id))
+                },
+                Err(_) => context.reply(format!("Usage: {}", self.usage())),
+            },
+            _ => context.reply(format!("Usage: {}", self.usage())),
+        }
     }
 }
 
@@ -254,11 +2174,15 @@ pub struct SendTweet {
     handle: Handle,
     token: Token,
     twitter: String,
-    last_message: RefCell<HashMap<String, String>>,
+    last_message: Rc<RefCell<HashMap<String, String>>>,
 }
 
 impl SendTweet {
-    pub fn new(config: &Config, handle: Handle) -> Option<SendTweet> {
+    /// Creates the handler if Twitter is configured. The last message in each channel is kept
+    /// in `last_message`, which is shared so that it survives a rehash.
+    pub fn new(
+        config: &Config, handle: Handle, last_message: Rc<RefCell<HashMap<String, String>>>,
+    ) -> Option<SendTweet> {
         let consumer = KeyPair::new(
             config.get_option("twitter_consumer_key")?.to_owned(),
             config.get_option("twitter_consumer_secret")?.to_owned(),
@@ -270,7 +2194,7 @@ impl SendTweet {
 
         let token = Token::Access { consumer, access };
         let twitter = config.get_option("twitter_name")?.to_owned();
-        Some(SendTweet { handle, token, twitter, last_message: RefCell::new(HashMap::new()) })
+        Some(SendTweet { handle, token, twitter, last_message })
     }
 }
 
@@ -279,6 +2203,14 @@ impl Handler for SendTweet {
         &["sendtweet"]
     }
 
+    fn usage(&self) -> &'static str {
+        "sendtweet"
+    }
+
+    fn summary(&self) -> &'static str {
+        "Posts the last message sent to this channel on Twitter."
+    }
+
     fn handle<'a>(&self, context: Context<'a>) -> Result<()> {
         if let Some(message) = self.last_message.borrow().get(context.respond_to) {
             if message.len() > 280 {
@@ -319,3 +2251,43 @@ impl Handler for SendTweet {
         Ok(())
     }
 }
+
+/// Splits a definition like "rust is a language" (or "crabs are crustaceans") into its name, verb,
+/// and value.
+fn split_definition(definition: &str) -> Option<(&str, &'static str, &str)> {
+    // lowercasing only ASCII keeps the offsets the same
+    let lower = definition.to_ascii_lowercase();
+    let (idx, verb) = [" is ", " are "].iter()
+        .filter_map(|sep| lower.find(sep).map(|idx| (idx, *sep)))
+        .min()?;
+    let (name, value) = (definition[..idx].trim(), definition[idx + verb.len()..].trim());
+    if name.is_empty() || value.is_empty() {
+        None
+    } else {
+        Some((name, verb.trim(), value))
+    }
+}
+
+/// Reads a `+name` or `-name` argument to iam, which sets or forgets a fact, returning the sign
+/// and the (lowercase) name.
+fn fact_name(arg: &str) -> Option<(char, String)> {
+    let sign = arg.chars().next()?;
+    let name = &arg[sign.len_utf8()..];
+    let valid = |c: char| c.is_alphanumeric() || c == '_' || c == '-';
+    if (sign == '+' || sign == '-') && !name.is_empty() && name.chars().all(valid) {
+        Some((sign, name.to_lowercase()))
+    } else {
+        None
+    }
+}
+
+/// Shortens `text` for quoting it back, e.g. in a delivery receipt.
+fn excerpt(text: &str) -> String {
+    const MAX_CHARS: usize = 50;
+
+    if text.chars().count() <= MAX_CHARS {
+        text.to_owned()
+    } else {
+        format!("{}...", text.chars().take(MAX_CHARS).collect::<String>().trim_right())
+    }
+}
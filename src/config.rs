@@ -0,0 +1,15 @@
+use std::str::FromStr;
+
+use irc::client::prelude::*;
+
+/// Reads an option from the configuration, ignoring it with a warning if it's invalid.
+pub fn option<T: FromStr>(config: &Config, name: &str) -> Option<T> {
+    let value = config.get_option(name)?;
+    match value.parse() {
+        Ok(value) => Some(value),
+        Err(_) => {
+            warn!("ignoring invalid value for {}: {}", name, value);
+            None
+        }
+    }
+}
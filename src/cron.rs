@@ -0,0 +1,213 @@
+use std::str::FromStr;
+
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Timelike};
+
+const MONTHS: &[&str] = &[
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: &[&str] = &["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// How far ahead to look for the next occurrence before deciding there isn't one, e.g. for a
+/// schedule on the 31st of February.
+const MAX_YEARS_AHEAD: i64 = 5;
+
+/// A recurring schedule written as a standard five-field cron expression (minute, hour, day of
+/// month, month, and day of week), interpreted in UTC. Fields may use `*`, lists (`1,15`), ranges
+/// (`1-5`), steps (`*/15`), and three-letter month and weekday names. The shorthands `@hourly`,
+/// `@daily`, `@weekly`, `@monthly`, and `@weekdays` are also accepted.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+#[derive(Debug, Fail)]
+#[fail(display = "invalid schedule: {} (expected e.g. \"0 10 * * mon-fri\")", _0)]
+pub struct InvalidCron(pub String);
+
+impl FromStr for Cron {
+    type Err = InvalidCron;
+
+    fn from_str(expr: &str) -> Result<Cron, InvalidCron> {
+        let expanded = match &expr.trim().to_lowercase()[..] {
+            "@hourly" => "0 * * * *".to_owned(),
+            "@daily" => "0 0 * * *".to_owned(),
+            "@weekly" => "0 0 * * sun".to_owned(),
+            "@monthly" => "0 0 1 * *".to_owned(),
+            "@weekdays" => "0 0 * * mon-fri".to_owned(),
+            other => other.to_owned(),
+        };
+
+        let fields: Vec<_> = expanded.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(InvalidCron(expr.to_owned()));
+        }
+
+        let invalid = || InvalidCron(expr.to_owned());
+        let weekdays = parse_field(fields[4], 0, 7, WEEKDAYS, 0).ok_or_else(&invalid)?;
+        Ok(Cron {
+            minutes: parse_field(fields[0], 0, 59, &[], 0).ok_or_else(&invalid)?,
+            hours: parse_field(fields[1], 0, 23, &[], 0).ok_or_else(&invalid)?,
+            days: parse_field(fields[2], 1, 31, &[], 0).ok_or_else(&invalid)?,
+            months: parse_field(fields[3], 1, 12, MONTHS, 1).ok_or_else(&invalid)?,
+            // both 0 and 7 mean Sunday
+            weekdays: (weekdays | (weekdays >> 7)) & 0x7f,
+            any_day: fields[2] == "*",
+            any_weekday: fields[4] == "*",
+        })
+    }
+}
+
+impl Cron {
+    /// Finds the first occurrence strictly after `after`.
+    pub fn next_after(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        let limit = after + Duration::days(366 * MAX_YEARS_AHEAD);
+        let mut time = after.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+
+        while time < limit {
+            if !has(self.months, time.month()) {
+                let (year, month) = if time.month() == 12 {
+                    (time.year() + 1, 1)
+                } else {
+                    (time.year(), time.month() + 1)
+                };
+                time = NaiveDate::from_ymd(year, month, 1).and_hms(0, 0, 0);
+            } else if !self.matches_day(time) {
+                time = time.date().succ().and_hms(0, 0, 0);
+            } else if !has(self.hours, time.hour()) {
+                time = time.date().and_hms(time.hour(), 0, 0) + Duration::hours(1);
+            } else if !has(self.minutes, time.minute()) {
+                time = time + Duration::minutes(1);
+            } else {
+                return Some(time);
+            }
+        }
+
+        None
+    }
+
+    /// Like cron, a day matches if either the day of month or the day of week does, unless one
+    /// of them is unrestricted.
+    fn matches_day(&self, time: NaiveDateTime) -> bool {
+        let day = has(self.days, time.day());
+        let weekday = has(self.weekdays, time.weekday().num_days_from_sunday());
+        match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (true, false) => weekday,
+            (false, true) => day,
+            (false, false) => day || weekday,
+        }
+    }
+}
+
+fn has(bits: u64, value: u32) -> bool {
+    bits & (1 << value) != 0
+}
+
+/// Parses a single field into a bitset of the values it allows. `names` are alternatives to
+/// numbers, with the first name standing for `first_name`.
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str], first_name: u32) -> Option<u64> {
+    let value = |s: &str| -> Option<u32> {
+        match names.iter().position(|name| *name == s) {
+            Some(idx) => Some(idx as u32 + first_name),
+            None => s.parse().ok(),
+        }
+    };
+
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.find('/') {
+            Some(idx) => (&part[..idx], part[idx + 1..].parse::<u32>().ok()?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            return None;
+        }
+
+        let (low, high) = if range == "*" {
+            (min, max)
+        } else if let Some(idx) = range.find('-') {
+            (value(&range[..idx])?, value(&range[idx + 1..])?)
+        } else {
+            // `5/15` means every 15 starting from 5
+            let start = value(range)?;
+            (start, if step > 1 { max } else { start })
+        };
+        if low < min || high > max || low > high {
+            return None;
+        }
+
+        let mut current = low;
+        while current <= high {
+            bits |= 1 << current;
+            current += step;
+        }
+    }
+
+    Some(bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd(2026, 10, day).and_hms(hour, minute, 0)
+    }
+
+    fn next(expr: &str, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        expr.parse::<Cron>().unwrap().next_after(after)
+    }
+
+    #[test]
+    fn invalid_schedules() {
+        for expr in &[
+            "", "bogus", "* * * *", "* * * * * *", "60 * * * *", "* 24 * * *", "0 0 0 * *",
+            "0 0 * 13 *", "0 0 * * 8", "*/0 * * * *", "5-1 * * * *", "0 0 * * mon-",
+        ] {
+            assert!(expr.parse::<Cron>().is_err(), "{:?} should be invalid", expr);
+        }
+    }
+
+    #[test]
+    fn names_and_shorthands() {
+        let parse = |expr: &str| expr.parse::<Cron>().unwrap();
+        assert_eq!(parse("0 10 * * MON-FRI"), parse("0 10 * * 1-5"));
+        assert_eq!(parse("0 0 * * 7"), parse("0 0 * * sun"));
+        assert_eq!(parse("0 0 1 jan,jul *"), parse("0 0 1 1,7 *"));
+        assert_eq!(parse("@daily"), parse("0 0 * * *"));
+        assert_eq!(parse(" @Weekdays "), parse("0 0 * * mon-fri"));
+    }
+
+    #[test]
+    fn next_occurrences() {
+        // 2026-10-18 is a Sunday
+        assert_eq!(next("0 10 * * mon-fri", at(18, 12, 0)), Some(at(19, 10, 0)));
+        assert_eq!(next("0 10 * * mon-fri", at(23, 10, 0)), Some(at(26, 10, 0)));
+        assert_eq!(next("0 12 * * *", at(18, 12, 0)), Some(at(19, 12, 0)));
+        assert_eq!(next("*/15 * * * *", at(18, 12, 7)), Some(at(18, 12, 15)));
+        assert_eq!(next("5/15 * * * *", at(18, 12, 5)), Some(at(18, 12, 20)));
+        assert_eq!(next("@weekly", at(18, 12, 0)), Some(at(25, 0, 0)));
+        assert_eq!(
+            next("0 0 1 jan *", at(18, 12, 0)),
+            Some(NaiveDate::from_ymd(2027, 1, 1).and_hms(0, 0, 0))
+        );
+    }
+
+    #[test]
+    fn day_of_month_or_week() {
+        // like cron, the 13th or any Friday rather than only Friday the 13th
+        assert_eq!(next("0 0 13 * fri", at(18, 12, 0)), Some(at(23, 0, 0)));
+    }
+
+    #[test]
+    fn impossible_dates_never_occur() {
+        assert_eq!(next("0 0 31 feb *", at(18, 12, 0)), None);
+        assert_eq!(next("0 0 31 4 *", at(18, 12, 0)), None);
+    }
+}
@@ -0,0 +1,307 @@
+use std::rc::Rc;
+
+use chrono::Utc;
+use diesel;
+use diesel::prelude::*;
+use diesel::result::{Error as QueryError};
+use diesel::sql_types::{BigInt, Text};
+use diesel::sqlite::SqliteConnection;
+
+use models::{NewWhoisAudit, NewWhoisEntry, NewWhoisFact, NewWhoisLock, NewWhoisRevision,
+             WhoisEntry, WhoisFact, WhoisMatch, WhoisRevision};
+use nick;
+use people::People;
+
+/// The descriptions people give of themselves with `iam`, keyed by person, along with any facts
+/// they add like "likes coffee". Every change to a description is kept as a revision, so that it
+/// can be undone when someone takes over a nickname and rewrites it, and owners can lock, forget,
+/// or rewrite any of them, which is recorded in an audit log.
+pub struct Descriptions {
+    conn: SqliteConnection,
+    people: Rc<People>,
+}
+
+impl Descriptions {
+    pub fn new(conn: SqliteConnection, people: Rc<People>) -> Descriptions {
+        Descriptions { conn, people }
+    }
+
+    /// Finds the key that the description of `nick` is stored under: the person it belongs to,
+    /// or `nick` itself if it was described before being grouped with anyone.
+    pub fn key_of(&self, nick: &str) -> QueryResult<String> {
+        use schema::{whois, whois_history};
+
+        let person = self.people.person_of(nick)?;
+        let key = nick::fold(nick);
+        if person == key {
+            return Ok(person);
+        }
+
+        let described = |key: &str| -> QueryResult<bool> {
+            Ok(whois::table.find(key).count().get_result::<i64>(&self.conn)? > 0 ||
+                whois_history::table
+                    .filter(whois_history::nickname_key.eq(key))
+                    .count()
+                    .get_result::<i64>(&self.conn)? > 0)
+        };
+        if !described(&person)? && described(&key)? {
+            Ok(key)
+        } else {
+            Ok(person)
+        }
+    }
+
+    /// Looks up the description of `nick`.
+    pub fn get(&self, nick: &str) -> QueryResult<Option<WhoisEntry>> {
+        use schema::whois;
+
+        let key = self.key_of(nick)?;
+        whois::table.find(&key[..]).first(&self.conn).optional()
+    }
+
+    /// Finds the services account that the description and facts of `nick` belong to, if any.
+    pub fn owner(&self, nick: &str) -> QueryResult<Option<String>> {
+        use schema::whois_facts::dsl::*;
+
+        if let Some(owner) = self.get(nick)?.and_then(|entry| entry.account) {
+            return Ok(Some(owner));
+        }
+        let person = self.people.person_of(nick)?;
+        let owner = whois_facts
+            .filter(nickname_key.eq(&person[..]))
+            .filter(account.is_not_null())
+            .select(account)
+            .first::<Option<String>>(&self.conn)
+            .optional()?;
+        Ok(owner.and_then(|owner| owner))
+    }
+
+    /// Lists the facts about `nick`, by name.
+    pub fn facts(&self, nick: &str) -> QueryResult<Vec<WhoisFact>> {
+        use schema::whois_facts::dsl::*;
+
+        let person = self.people.person_of(nick)?;
+        whois_facts.filter(nickname_key.eq(&person[..])).order(name).load(&self.conn)
+    }
+
+    /// Looks up a single fact about `nick`.
+    pub fn fact(&self, nick: &str, fact: &str) -> QueryResult<Option<WhoisFact>> {
+        use schema::whois_facts::dsl::*;
+
+        let person = self.people.person_of(nick)?;
+        whois_facts.find((&person[..], &fact.to_lowercase()[..])).first(&self.conn).optional()
+    }
+
+    /// Sets a fact about the person `key`, or removes it if `value` is `None`. Returns whether
+    /// there was anything to change.
+    pub fn set_fact(
+        &self, key: &str, nickname: &str, fact: &str, value: Option<&str>, account: Option<&str>,
+    ) -> QueryResult<bool> {
+        use schema::whois_facts;
+
+        let name = fact.to_lowercase();
+        match value {
+            Some(value) => diesel::replace_into(whois_facts::table)
+                .values(&NewWhoisFact { nickname_key: key, name: &name, nickname, value, account })
+                .execute(&self.conn)
+                .map(|_| true),
+            None => diesel::delete(whois_facts::table.find((key, &name[..])))
+                .execute(&self.conn)
+                .map(|deleted| deleted > 0),
+        }
+    }
+
+    /// Finds descriptions and facts containing every word of `query`, best matches first.
+    pub fn search(&self, query: &str, limit: i64) -> QueryResult<Vec<WhoisMatch>> {
+        // each word is quoted, so that nothing in it is taken for FTS5 query syntax
+        let query = query.split_whitespace()
+            .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+            .collect::<Vec<_>>()
+            .join(" ");
+        diesel::sql_query(
+            "SELECT nickname_key, nickname, field FROM whois_search WHERE whois_search MATCH ? \
+             ORDER BY rank LIMIT ?"
+        ).bind::<Text, _>(query).bind::<BigInt, _>(limit).load(&self.conn)
+    }
+
+    /// Sets the description stored under `key`, or removes it if `description` is `None`, and
+    /// records the change as a new revision. The description then belongs to `account`.
+    pub fn set(
+        &self, key: &str, nickname: &str, description: Option<&str>, changed_by: &str,
+        account: Option<&str>,
+    ) -> QueryResult<()> {
+        self.conn.transaction::<_, QueryError, _>(|| {
+            self.write(key, nickname, description, changed_by, account)
+        })
+    }
+
+    /// Lists the most recent revisions of the description of `nick`, newest first.
+    pub fn history(&self, nick: &str, limit: i64) -> QueryResult<Vec<WhoisRevision>> {
+        use schema::whois_history::dsl::*;
+
+        let key = self.key_of(nick)?;
+        whois_history
+            .filter(nickname_key.eq(&key[..]))
+            .order(id.desc())
+            .limit(limit)
+            .load(&self.conn)
+    }
+
+    /// Restores the description of `nick` as of `revision`, or as it was before the latest
+    /// change if that's `None`. Returns the revision that was restored, if there was one. The
+    /// description stays with the account it already belonged to.
+    pub fn revert(
+        &self, nick: &str, revision: Option<i32>, changed_by: &str,
+    ) -> QueryResult<Option<WhoisRevision>> {
+        use schema::{whois, whois_history};
+
+        let key = self.key_of(nick)?;
+        self.conn.transaction::<_, QueryError, _>(|| {
+            let owner = whois::table
+                .find(&key[..])
+                .select(whois::account)
+                .first::<Option<String>>(&self.conn)
+                .optional()?
+                .and_then(|owner| owner);
+
+            let history = whois_history::table.filter(whois_history::nickname_key.eq(&key[..]));
+            let restored: Option<WhoisRevision> = match revision {
+                Some(revision) => history.filter(whois_history::id.eq(revision)).first(&self.conn),
+                None => history.order(whois_history::id.desc()).offset(1).first(&self.conn),
+            }.optional()?;
+
+            if let Some(ref restored) = restored {
+                self.write(
+                    &key, &restored.nickname,
+                    restored.description.as_ref().map(|s| &s[..]), changed_by,
+                    owner.as_ref().map(|s| &s[..]),
+                )?;
+            }
+            Ok(restored)
+        })
+    }
+
+    /// Checks whether an owner has locked the description of `nick`.
+    pub fn is_locked(&self, nick: &str) -> QueryResult<bool> {
+        use schema::whois_locks;
+
+        let person = self.people.person_of(nick)?;
+        Ok(whois_locks::table.find(&person[..]).count().get_result::<i64>(&self.conn)? > 0)
+    }
+
+    /// Locks or unlocks the description of `nick`, returning whether that changed anything.
+    pub fn lock(&self, nick: &str, locked: bool, changed_by: &str) -> QueryResult<bool> {
+        use schema::whois_locks;
+
+        let person = self.people.person_of(nick)?;
+        self.conn.transaction::<_, QueryError, _>(|| {
+            let changed = if !locked {
+                diesel::delete(whois_locks::table.find(&person[..])).execute(&self.conn)? > 0
+            } else if self.is_locked(nick)? {
+                false
+            } else {
+                diesel::insert_into(whois_locks::table).values(&NewWhoisLock {
+                    nickname_key: &person,
+                    locked_by: changed_by,
+                    locked: &Utc::now().naive_utc(),
+                }).execute(&self.conn)?;
+                true
+            };
+
+            if changed {
+                let action = if locked { "lock" } else { "unlock" };
+                self.audit(&person, nick, action, None, changed_by)?;
+            }
+            Ok(changed)
+        })
+    }
+
+    /// Removes the description and facts of `nick`, and the text of every earlier revision of
+    /// the description wherever it was kept, returning whether there were any.
+    pub fn forget(&self, nick: &str, changed_by: &str) -> QueryResult<bool> {
+        use schema::{whois_audit, whois_facts, whois_history};
+
+        let key = self.key_of(nick)?;
+        let person = self.people.person_of(nick)?;
+        self.conn.transaction::<_, QueryError, _>(|| {
+            let description = self.get(nick)?.map(|entry| entry.description);
+            let facts = diesel::delete(
+                whois_facts::table.filter(whois_facts::nickname_key.eq(&person[..]))
+            ).execute(&self.conn)?;
+            // otherwise reverting would bring back what was forgotten
+            let revisions = diesel::update(
+                whois_history::table
+                    .filter(whois_history::nickname_key.eq(&key[..]))
+                    .filter(whois_history::description.is_not_null())
+            ).set(whois_history::description.eq(None::<String>)).execute(&self.conn)?;
+            if description.is_none() && facts == 0 && revisions == 0 {
+                return Ok(false);
+            }
+
+            if description.is_some() {
+                self.write(&key, nick, None, changed_by, None)?;
+            }
+            // the audit log records that descriptions were set, but not what they said anymore
+            diesel::update(
+                whois_audit::table
+                    .filter(whois_audit::nickname_key.eq(&key[..]))
+                    .filter(whois_audit::action.eq("set"))
+            ).set(whois_audit::detail.eq(None::<String>)).execute(&self.conn)?;
+            self.audit(&key, nick, "forget", None, changed_by)?;
+            Ok(true)
+        })
+    }
+
+    /// Rewrites the description of `nick` on their behalf. It stays with the account it already
+    /// belonged to.
+    pub fn replace(&self, nick: &str, description: &str, changed_by: &str) -> QueryResult<()> {
+        let key = self.key_of(nick)?;
+        self.conn.transaction::<_, QueryError, _>(|| {
+            let owner = self.get(nick)?.and_then(|entry| entry.account);
+            self.write(
+                &key, nick, Some(description), changed_by, owner.as_ref().map(|s| &s[..]),
+            )?;
+            self.audit(&key, nick, "set", Some(description), changed_by)
+        })
+    }
+
+    /// Records a change an owner made to someone else's description or facts.
+    pub fn audit(
+        &self, key: &str, nickname: &str, action: &str, detail: Option<&str>, changed_by: &str,
+    ) -> QueryResult<()> {
+        use schema::whois_audit;
+
+        info!("{} used whois {} on {}", changed_by, action, nickname);
+        diesel::insert_into(whois_audit::table).values(&NewWhoisAudit {
+            nickname_key: key,
+            nickname,
+            action,
+            detail,
+            changed_by,
+            changed: &Utc::now().naive_utc(),
+        }).execute(&self.conn)?;
+        Ok(())
+    }
+
+    fn write(
+        &self, key: &str, nickname: &str, description: Option<&str>, changed_by: &str,
+        account: Option<&str>,
+    ) -> QueryResult<()> {
+        use schema::{whois, whois_history};
+
+        match description {
+            Some(description) => diesel::replace_into(whois::table)
+                .values(&NewWhoisEntry { nickname, description, nickname_key: key, account })
+                .execute(&self.conn)?,
+            None => diesel::delete(whois::table.find(key)).execute(&self.conn)?,
+        };
+        diesel::insert_into(whois_history::table).values(&NewWhoisRevision {
+            nickname_key: key,
+            nickname,
+            description,
+            changed_by,
+            changed: &Utc::now().naive_utc(),
+        }).execute(&self.conn)?;
+        Ok(())
+    }
+}
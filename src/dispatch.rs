@@ -1,28 +1,124 @@
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
 use std::ops::Deref;
 use std::rc::Rc;
 use std::sync::Arc;
 
-use irc::client::IrcClient;
+use irc::client::prelude::*;
 use irc::error::Result;
 
+use args::{self, ArgError, Args, Param};
+use perms::{self, Permissions, Role};
+use roster::Roster;
+
 #[derive(Copy, Clone)]
 pub struct Context<'a> {
     pub client: &'a IrcClient,
+    /// The message being handled.
+    pub message: &'a Message,
+    /// Who is in each of the channels we are in.
+    pub roster: &'a Roster,
     pub sender: &'a str,
     pub respond_to: &'a str,
     pub args: &'a [&'a str],
+    /// The raw text following the command, used when parsing typed arguments.
+    pub line: &'a str,
     pub msg: &'a str,
+    /// Used to check the sender's role, for handlers that allow more to some roles than others.
+    pub permissions: Option<&'a Permissions>,
+    /// The character that starts a command, for telling people what to type.
+    pub line_start: char,
+}
+
+impl<'a> Context<'a> {
+    /// Parses the arguments to the current command according to `params`.
+    pub fn parse(&self, params: &[Param]) -> ::std::result::Result<Args, ArgError> {
+        args::parse(self.line, params)
+    }
+
+    /// Responds to the sender, addressing them by name.
+    pub fn reply<S: Display>(&self, msg: S) -> Result<()> {
+        self.client.send_privmsg(self.respond_to, format!("{}: {}", self.sender, msg))
+    }
+
+    /// Finds the services account the sender is identified to, from the message's `account` tag
+    /// or else from what the roster has learned about them.
+    pub fn account(&self) -> Option<String> {
+        perms::account_of(self.message).map(|account| account.to_owned())
+            .or_else(|| {
+                // an account restored from before we reconnected may belong to someone else now
+                self.roster.user(self.sender).and_then(|user| {
+                    if user.stale { None } else { user.account }
+                })
+            })
+    }
+
+    /// Finds the most privileged role the sender has, which is `Everyone` without permissions.
+    pub fn role(&self) -> Result<Role> {
+        match self.permissions {
+            Some(permissions) => {
+                permissions.role_of(self.message, self.account().as_ref().map(|s| &s[..]))
+            }
+            None => Ok(Role::Everyone),
+        }
+    }
 }
 
 pub trait Handler {
     fn command(&self) -> &'static [&'static str];
 
+    /// A synopsis of the command's arguments, starting with the command name.
+    fn usage(&self) -> &'static str {
+        ""
+    }
+
+    /// A one-sentence description of what the command does.
+    fn summary(&self) -> &'static str {
+        ""
+    }
+
+    /// Complete example invocations of the command, without the command prefix.
+    fn examples(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// The role a user must have to run the command.
+    fn role(&self) -> Role {
+        Role::Everyone
+    }
+
     fn handle<'a>(&self, context: Context<'a>) -> Result<()>;
 
     fn on_each_message<'a>(&self, _: Context<'a>) -> Result<()> {
         Ok(())
     }
+
+    /// Called when someone joins a channel, with `respond_to` set to the channel.
+    fn on_join<'a>(&self, _: Context<'a>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called when someone leaves a channel, with `respond_to` set to the channel and `msg` set
+    /// to their reason, if any.
+    fn on_part<'a>(&self, _: Context<'a>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called when someone disconnects, with `msg` set to their reason, if any.
+    fn on_quit<'a>(&self, _: Context<'a>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called when someone changes their nickname, with `sender` set to their new nickname.
+    fn on_nick<'a>(&self, _: Context<'a>, _old_nick: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called for every other message that is not a `PRIVMSG`.
+    fn on_event<'a>(&self, _: Context<'a>) -> Result<()> {
+        Ok(())
+    }
 }
 
 impl<T> Handler for Rc<T> where T: Handler {
@@ -30,6 +126,22 @@ impl<T> Handler for Rc<T> where T: Handler {
         self.deref().command()
     }
 
+    fn usage(&self) -> &'static str {
+        self.deref().usage()
+    }
+
+    fn summary(&self) -> &'static str {
+        self.deref().summary()
+    }
+
+    fn examples(&self) -> &'static [&'static str] {
+        self.deref().examples()
+    }
+
+    fn role(&self) -> Role {
+        self.deref().role()
+    }
+
     fn handle<'a>(&self, context: Context<'a>) -> Result<()> {
         self.deref().handle(context)
     }
@@ -37,6 +149,26 @@ impl<T> Handler for Rc<T> where T: Handler {
     fn on_each_message<'a>(&self, context: Context<'a>) -> Result<()> {
         self.deref().on_each_message(context)
     }
+
+    fn on_join<'a>(&self, context: Context<'a>) -> Result<()> {
+        self.deref().on_join(context)
+    }
+
+    fn on_part<'a>(&self, context: Context<'a>) -> Result<()> {
+        self.deref().on_part(context)
+    }
+
+    fn on_quit<'a>(&self, context: Context<'a>) -> Result<()> {
+        self.deref().on_quit(context)
+    }
+
+    fn on_nick<'a>(&self, context: Context<'a>, old_nick: &str) -> Result<()> {
+        self.deref().on_nick(context, old_nick)
+    }
+
+    fn on_event<'a>(&self, context: Context<'a>) -> Result<()> {
+        self.deref().on_event(context)
+    }
 }
 
 impl<T> Handler for Arc<T> where T: Handler {
@@ -44,6 +176,22 @@ impl<T> Handler for Arc<T> where T: Handler {
         self.deref().command()
     }
 
+    fn usage(&self) -> &'static str {
+        self.deref().usage()
+    }
+
+    fn summary(&self) -> &'static str {
+        self.deref().summary()
+    }
+
+    fn examples(&self) -> &'static [&'static str] {
+        self.deref().examples()
+    }
+
+    fn role(&self) -> Role {
+        self.deref().role()
+    }
+
     fn handle<'a>(&self, context: Context<'a>) -> Result<()> {
         self.deref().handle(context)
     }
@@ -51,6 +199,26 @@ impl<T> Handler for Arc<T> where T: Handler {
     fn on_each_message<'a>(&self, context: Context<'a>) -> Result<()> {
         self.deref().on_each_message(context)
     }
+
+    fn on_join<'a>(&self, context: Context<'a>) -> Result<()> {
+        self.deref().on_join(context)
+    }
+
+    fn on_part<'a>(&self, context: Context<'a>) -> Result<()> {
+        self.deref().on_part(context)
+    }
+
+    fn on_quit<'a>(&self, context: Context<'a>) -> Result<()> {
+        self.deref().on_quit(context)
+    }
+
+    fn on_nick<'a>(&self, context: Context<'a>, old_nick: &str) -> Result<()> {
+        self.deref().on_nick(context, old_nick)
+    }
+
+    fn on_event<'a>(&self, context: Context<'a>) -> Result<()> {
+        self.deref().on_event(context)
+    }
 }
 
 impl<T> Handler for Option<T> where T: Handler {
@@ -61,6 +229,34 @@ impl<T> Handler for Option<T> where T: Handler {
         }
     }
 
+    fn usage(&self) -> &'static str {
+        match *self {
+            Some(ref handler) => handler.usage(),
+            None => ""
+        }
+    }
+
+    fn summary(&self) -> &'static str {
+        match *self {
+            Some(ref handler) => handler.summary(),
+            None => ""
+        }
+    }
+
+    fn examples(&self) -> &'static [&'static str] {
+        match *self {
+            Some(ref handler) => handler.examples(),
+            None => &[]
+        }
+    }
+
+    fn role(&self) -> Role {
+        match *self {
+            Some(ref handler) => handler.role(),
+            None => Role::Everyone
+        }
+    }
+
     fn handle<'a>(&self, context: Context<'a>) -> Result<()> {
         match *self {
             Some(ref handler) => handler.handle(context),
@@ -74,12 +270,109 @@ impl<T> Handler for Option<T> where T: Handler {
             None => Ok(())
         }
     }
+
+    fn on_join<'a>(&self, context: Context<'a>) -> Result<()> {
+        match *self {
+            Some(ref handler) => handler.on_join(context),
+            None => Ok(())
+        }
+    }
+
+    fn on_part<'a>(&self, context: Context<'a>) -> Result<()> {
+        match *self {
+            Some(ref handler) => handler.on_part(context),
+            None => Ok(())
+        }
+    }
+
+    fn on_quit<'a>(&self, context: Context<'a>) -> Result<()> {
+        match *self {
+            Some(ref handler) => handler.on_quit(context),
+            None => Ok(())
+        }
+    }
+
+    fn on_nick<'a>(&self, context: Context<'a>, old_nick: &str) -> Result<()> {
+        match *self {
+            Some(ref handler) => handler.on_nick(context, old_nick),
+            None => Ok(())
+        }
+    }
+
+    fn on_event<'a>(&self, context: Context<'a>) -> Result<()> {
+        match *self {
+            Some(ref handler) => handler.on_event(context),
+            None => Ok(())
+        }
+    }
+}
+
+/// Tracks consecutive failures of each hook of each handler by name, disabling handlers with a
+/// hook that fails too often. Hooks are counted apart, so that a command which keeps failing
+/// isn't kept alive by the same handler quietly succeeding on every message it sees.
+pub struct Health {
+    limit: Cell<usize>,
+    failures: RefCell<HashMap<(&'static str, &'static str), usize>>,
+    disabled: RefCell<HashSet<&'static str>>,
+}
+
+impl Health {
+    fn new() -> Health {
+        Health {
+            limit: Cell::new(5),
+            failures: RefCell::new(HashMap::new()),
+            disabled: RefCell::new(HashSet::new()),
+        }
+    }
+
+    pub fn is_disabled(&self, name: &str) -> bool {
+        self.disabled.borrow().contains(name)
+    }
+
+    /// Lists the names of every disabled handler.
+    pub fn disabled(&self) -> Vec<&'static str> {
+        let mut disabled: Vec<_> = self.disabled.borrow().iter().cloned().collect();
+        disabled.sort();
+        disabled
+    }
+
+    /// Re-enables a handler, returning whether it was disabled.
+    pub fn enable(&self, name: &str) -> bool {
+        self.failures.borrow_mut().retain(|&(handler, _), _| handler != name);
+        self.disabled.borrow_mut().remove(name)
+    }
+
+    fn succeeded(&self, name: &'static str, hook: &'static str) {
+        self.failures.borrow_mut().remove(&(name, hook));
+    }
+
+    /// Records a failure of `hook`, returning whether the handler was disabled as a result.
+    fn failed(&self, name: &'static str, hook: &'static str) -> bool {
+        let mut failures = self.failures.borrow_mut();
+        let count = failures.entry((name, hook)).or_insert(0);
+        *count += 1;
+        if *count >= self.limit.get() {
+            *count = 0;
+            self.disabled.borrow_mut().insert(name);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// The name a handler is known by in logs and when enabling it, which is its first command.
+fn name_of(handler: &Handler) -> &'static str {
+    handler.command().first().cloned().unwrap_or("unnamed")
 }
 
 pub struct Dispatcher {
     line_start: char,
     handlers: Vec<Box<Handler>>,
     cmd_map: HashMap<&'static str, usize>,
+    permissions: Option<Permissions>,
+    health: Rc<Health>,
+    roster: Rc<Roster>,
 }
 
 impl Dispatcher {
@@ -88,9 +381,28 @@ impl Dispatcher {
             line_start,
             handlers: Vec::new(),
             cmd_map: HashMap::new(),
+            permissions: None,
+            health: Rc::new(Health::new()),
+            roster: Rc::new(Roster::default()),
         }
     }
 
+    /// Sets the roster made available to handlers, which is otherwise empty.
+    pub fn set_roster(&mut self, roster: Rc<Roster>) {
+        self.roster = roster;
+    }
+
+    /// Sets how many consecutive failures a handler may have before it is disabled.
+    pub fn set_failure_limit(&mut self, limit: usize) {
+        self.health.limit.set(limit);
+    }
+
+    /// Sets the permissions used to check roles. Without them, only commands available to
+    /// everyone can be run.
+    pub fn set_permissions(&mut self, permissions: Permissions) {
+        self.permissions = Some(permissions);
+    }
+
     pub fn register<H>(&mut self, handler: H) where H: Handler + 'static {
         for cmd in handler.command() {
             self.cmd_map.insert(cmd, self.handlers.len());
@@ -102,47 +414,357 @@ impl Dispatcher {
         self.cmd_map.get(command).map(|idx| &*self.handlers[*idx])
     }
 
+    /// Passes a message other than a `PRIVMSG` to the matching hook of every handler.
+    pub fn dispatch_event(&self, client: &IrcClient, raw: &Message) -> Result<()> {
+        let sender = raw.source_nickname().unwrap_or("");
+        let context = Context {
+            client, sender,
+            message: raw,
+            roster: &self.roster,
+            respond_to: sender,
+            args: &[],
+            line: "",
+            msg: "",
+            permissions: self.permissions.as_ref(),
+            line_start: self.line_start,
+        };
+
+        match raw.command {
+            Command::JOIN(ref chanlist, _, _) => for chan in chanlist.split(',') {
+                let context = Context { respond_to: chan, .. context };
+                self.broadcast("on_join", context, |handler, context| handler.on_join(context));
+            },
+            Command::PART(ref chanlist, ref reason) => for chan in chanlist.split(',') {
+                let msg = reason.as_ref().map(|s| &s[..]).unwrap_or("");
+                let context = Context { respond_to: chan, msg, .. context };
+                self.broadcast("on_part", context, |handler, context| handler.on_part(context));
+            },
+            Command::QUIT(ref reason) => {
+                let msg = reason.as_ref().map(|s| &s[..]).unwrap_or("");
+                let context = Context { msg, .. context };
+                self.broadcast("on_quit", context, |handler, context| handler.on_quit(context));
+            }
+            Command::NICK(ref new_nick) => {
+                let new_context = Context { sender: new_nick, respond_to: new_nick, .. context };
+                self.broadcast("on_nick", new_context, |handler, context| {
+                    handler.on_nick(context, sender)
+                });
+            }
+            _ => self.broadcast("on_event", context, |handler, context| handler.on_event(context)),
+        }
+
+        Ok(())
+    }
+
+    /// Calls a hook on every enabled handler. Failures are logged and counted, but not reported
+    /// to anyone, since nobody asked for a response.
+    fn broadcast<'a, F>(&self, hook_name: &'static str, context: Context<'a>, hook: F)
+    where F: Fn(&Handler, Context<'a>) -> Result<()> {
+        for handler in &self.handlers {
+            let name = name_of(&**handler);
+            if self.health.is_disabled(name) {
+                continue;
+            }
+
+            match hook(&**handler, context) {
+                Ok(()) => self.health.succeeded(name, hook_name),
+                Err(e) => {
+                    error!(
+                        "{} failed in {} on {:?} from {} in {}: {}",
+                        name, hook_name, context.message.to_string().trim_right(), context.sender,
+                        context.respond_to, e
+                    );
+                    if self.health.failed(name, hook_name) {
+                        warn!("disabled {} after repeated failures", name);
+                    }
+                }
+            }
+        }
+    }
+
     pub fn dispatch(
-        &self, client: &IrcClient, sender: &str, respond_to: &str, message: &str,
+        &self, client: &IrcClient, raw: &Message, sender: &str, respond_to: &str, message: &str,
     ) -> Result<()> {
         if !message.starts_with(self.line_start) {
-            for handler in &self.handlers {
-                handler.on_each_message(Context {
-                    client, sender, respond_to,
-                    args: &[],
-                    msg: message,
-                })?;
-            }
+            let context = Context {
+                client, sender, respond_to,
+                message: raw,
+                roster: &self.roster,
+                args: &[],
+                line: "",
+                msg: message,
+                permissions: self.permissions.as_ref(),
+                line_start: self.line_start,
+            };
+
+            self.broadcast("on_each_message", context, |handler, context| {
+                handler.on_each_message(context)
+            });
             return Ok(())
         }
 
-        let message = &message[1..];
-        let fragments: Vec<_> = message.split(' ').collect();
-        if fragments.is_empty() {
+        let message = &message[self.line_start.len_utf8()..];
+        let (command, line) = match message.find(char::is_whitespace) {
+            Some(idx) => (&message[..idx], message[idx..].trim()),
+            None => (message, ""),
+        };
+        if command.is_empty() {
             return Ok(())
         }
 
-        let command = fragments[0];
+        let tokens = args::tokenize(line);
+        let fragments: Vec<_> = tokens.iter().map(|token| &token.value[..]).collect();
         let context = Context {
             client, sender, respond_to,
-            args: &fragments[1..],
+            message: raw,
+            roster: &self.roster,
+            args: &fragments,
+            line,
             msg: message,
+            permissions: self.permissions.as_ref(),
+            line_start: self.line_start,
         };
 
+        let handler = match self.get_handler(command) {
+            Some(handler) => handler,
+            None => return Ok(()),
+        };
 
-        self.get_handler(command).map(|handler| handler.handle(context)).unwrap_or(Ok(()))
+        let name = name_of(handler);
+        if self.health.is_disabled(name) {
+            return context.reply(format!("Sorry, {} is disabled right now.", command));
+        }
+
+        let required = handler.role();
+        if required > Role::Everyone {
+            let role = match context.role() {
+                Ok(role) => role,
+                Err(e) => {
+                    error!("failed to check the role of {}: {}", sender, e);
+                    return context.reply("Sorry, I couldn't check your permissions.");
+                }
+            };
+
+            if role < required {
+                warn!("refused {} from {} with role {}", command, sender, role);
+                return context.reply(
+                    format!("Sorry, {} requires the {} role.", command, required)
+                );
+            }
+        }
+
+        match handler.handle(context) {
+            Ok(()) => {
+                self.health.succeeded(name, "handle");
+                Ok(())
+            }
+            Err(e) => {
+                error!(
+                    "{} failed on {:?} from {} in {}: {}", name, message, sender, respond_to, e
+                );
+                if self.health.failed(name, "handle") {
+                    warn!("disabled {} after repeated failures", name);
+                    context.reply(format!(
+                        "Sorry, something went wrong, and {} has been disabled until an owner \
+                         re-enables it.", command
+                    ))
+                } else {
+                    context.reply(format!("Sorry, something went wrong while running {}.", command))
+                }
+            }
+        }
+    }
+}
+
+/// A built-in handler that re-enables handlers disabled after failing repeatedly.
+pub struct Enable {
+    health: Rc<Health>,
+}
+
+impl<'a> From<&'a Dispatcher> for Enable {
+    fn from(dispatcher: &'a Dispatcher) -> Enable {
+        Enable { health: dispatcher.health.clone() }
+    }
+}
+
+impl Handler for Enable {
+    fn command(&self) -> &'static [&'static str] {
+        &["enable"]
+    }
+
+    fn usage(&self) -> &'static str {
+        "enable [command]"
+    }
+
+    fn summary(&self) -> &'static str {
+        "Re-enables a command that was disabled after failing, or lists the disabled commands."
+    }
+
+    fn role(&self) -> Role {
+        Role::Owner
+    }
+
+    fn handle<'a>(&self, context: Context<'a>) -> Result<()> {
+        let args = match context.parse(&[Param::Optional("command")]) {
+            Ok(args) => args,
+            Err(e) => return context.reply(format!("{}. Usage: {}", e, self.usage())),
+        };
+
+        match args.get("command") {
+            Some(command) => if self.health.enable(command) {
+                info!("{} re-enabled {}", context.sender, command);
+                context.reply(format!("{} is enabled again.", command))
+            } else {
+                context.reply(format!("{} isn't disabled.", command))
+            },
+            None => {
+                let disabled = self.health.disabled();
+                if disabled.is_empty() {
+                    context.reply("Nothing is disabled.")
+                } else {
+                    context.reply(format!("Disabled: {}", disabled.join(", ")))
+                }
+            }
+        }
+    }
+}
+
+struct HelpEntry {
+    aliases: Vec<&'static str>,
+    usage: &'static str,
+    summary: &'static str,
+    examples: &'static [&'static str],
+}
+
+/// A built-in handler that describes every command registered with a `Dispatcher`.
+pub struct Help {
+    line_start: char,
+    entries: Vec<HelpEntry>,
+}
+
+impl<'a> From<&'a Dispatcher> for Help {
+    fn from(dispatcher: &'a Dispatcher) -> Help {
+        let mut entries: Vec<_> = dispatcher.handlers.iter().enumerate().filter_map(
+            |(idx, handler)| {
+                // commands claimed by a handler registered later are no longer aliases of this one
+                let aliases: Vec<_> = handler.command().iter().cloned().filter(|cmd| {
+                    dispatcher.cmd_map.get(cmd) == Some(&idx)
+                }).collect();
+
+                if aliases.is_empty() {
+                    None
+                } else {
+                    Some(HelpEntry {
+                        aliases,
+                        usage: handler.usage(),
+                        summary: handler.summary(),
+                        examples: handler.examples(),
+                    })
+                }
+            }
+        ).collect();
+
+        let help = Help { line_start: dispatcher.line_start, entries: Vec::new() };
+        entries.push(HelpEntry {
+            aliases: help.command().to_vec(),
+            usage: help.usage(),
+            summary: help.summary(),
+            examples: help.examples(),
+        });
+        entries.sort_by_key(|entry| entry.aliases[0]);
+
+        Help { entries, .. help }
+    }
+}
+
+impl Handler for Help {
+    fn command(&self) -> &'static [&'static str] {
+        &["help"]
+    }
+
+    fn usage(&self) -> &'static str {
+        "help [command]"
+    }
+
+    fn summary(&self) -> &'static str {
+        "Lists every command, or explains how to use one of them."
+    }
+
+    fn examples(&self) -> &'static [&'static str] {
+        &["help", "help tell"]
+    }
+
+    fn handle<'a>(&self, context: Context<'a>) -> Result<()> {
+        let args = match context.parse(&[Param::Optional("command")]) {
+            Ok(args) => args,
+            Err(e) => return context.reply(format!("{}. Usage: {}", e, self.usage())),
+        };
+
+        let command = match args.get("command") {
+            Some(command) => command.trim_left_matches(self.line_start),
+            None => {
+                let commands: Vec<_> = self.entries.iter().map(|entry| {
+                    format!("{}{}", self.line_start, entry.aliases[0])
+                }).collect();
+
+                return context.reply(format!(
+                    "I know {}. Try {}help <command> to learn more about one of them.",
+                    commands.join(", "), self.line_start
+                ));
+            }
+        };
+
+        let entry = match self.entries.iter().find(|entry| {
+            entry.aliases.iter().any(|alias| *alias == command)
+        }) {
+            Some(entry) => entry,
+            None => return context.reply(format!("I don't know the command {}.", command)),
+        };
+
+        let usage = if entry.usage.is_empty() { entry.aliases[0] } else { entry.usage };
+        if entry.summary.is_empty() {
+            context.reply(format!("{}{}", self.line_start, usage))?;
+        } else {
+            context.reply(format!("{}{} - {}", self.line_start, usage, entry.summary))?;
+        }
+
+        if entry.aliases.len() > 1 {
+            let aliases: Vec<_> = entry.aliases.iter().map(|alias| {
+                format!("{}{}", self.line_start, alias)
+            }).collect();
+            context.reply(format!("Also known as {}.", aliases.join(", ")))?;
+        }
+
+        for example in entry.examples {
+            context.reply(format!("Example: {}{}", self.line_start, example))?;
+        }
+
+        Ok(())
     }
 }
 
 #[macro_export]
 macro_rules! dispatcher {
-    ( $s:expr ) => (Dispatcher::new($s));
+    ( $s:expr ) => {
+        {
+            let mut temp_dispatcher = Dispatcher::new($s);
+            let enable = Enable::from(&temp_dispatcher);
+            temp_dispatcher.register(enable);
+            let help = Help::from(&temp_dispatcher);
+            temp_dispatcher.register(help);
+            temp_dispatcher
+        }
+    };
     ( $s:expr, $( $x:expr ),* $(,)* ) => {
         {
             let mut temp_dispatcher = Dispatcher::new($s);
             $(
                 temp_dispatcher.register($x);
             )*
+            let enable = Enable::from(&temp_dispatcher);
+            temp_dispatcher.register(enable);
+            let help = Help::from(&temp_dispatcher);
+            temp_dispatcher.register(help);
             temp_dispatcher
         }
     };
@@ -0,0 +1,131 @@
+use chrono::Utc;
+use diesel;
+use diesel::prelude::*;
+use diesel::result::{Error as QueryError};
+use diesel::sqlite::SqliteConnection;
+
+use models::{Factoid, FactoidRevision, NewFactoid, NewFactoidRevision};
+use nick;
+
+/// The scope of factoids that apply in every channel.
+pub const GLOBAL: &str = "";
+
+/// Puts a factoid name in the form it's stored under, so that "Rust  Lang" and "rust lang" are
+/// the same factoid.
+pub fn normalize(name: &str) -> String {
+    name.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+/// Finds the scope of factoids learned in `channel`, which is global if it's really a query.
+pub fn scope_of(channel: &str) -> String {
+    if channel.starts_with('#') || channel.starts_with('&') {
+        nick::fold(channel)
+    } else {
+        GLOBAL.to_owned()
+    }
+}
+
+/// Infobot-style factoids, each belonging to the channel it was learned in or to every channel.
+/// Every change is kept as a revision.
+pub struct Factoids {
+    conn: SqliteConnection,
+}
+
+impl From<SqliteConnection> for Factoids {
+    fn from(conn: SqliteConnection) -> Factoids {
+        Factoids { conn }
+    }
+}
+
+impl Factoids {
+    /// Looks up a factoid as it applies in `channel`: the one learned there, or else a global
+    /// one.
+    pub fn find(&self, channel: &str, name: &str) -> QueryResult<Option<Factoid>> {
+        let scope = scope_of(channel);
+        if scope != GLOBAL {
+            if let Some(factoid) = self.get(&scope, name)? {
+                return Ok(Some(factoid));
+            }
+        }
+        self.get(GLOBAL, name)
+    }
+
+    /// Looks up a factoid in exactly `scope`.
+    pub fn get(&self, scope: &str, name: &str) -> QueryResult<Option<Factoid>> {
+        use schema::factoids;
+
+        factoids::table.find((scope, &normalize(name)[..])).first(&self.conn).optional()
+    }
+
+    /// Learns a factoid, replacing any with the same name in `scope` but keeping it locked if
+    /// it was.
+    pub fn learn(
+        &self, scope: &str, name: &str, verb: &str, value: &str, learned_by: &str,
+    ) -> QueryResult<()> {
+        use schema::factoids;
+
+        let name = normalize(name);
+        self.conn.transaction::<_, QueryError, _>(|| {
+            let locked = self.get(scope, &name)?.map(|factoid| factoid.locked).unwrap_or(false);
+            diesel::replace_into(factoids::table).values(&NewFactoid {
+                channel: scope,
+                name: &name,
+                verb,
+                value,
+                created_by: learned_by,
+                created: &Utc::now().naive_utc(),
+                locked,
+            }).execute(&self.conn)?;
+            self.record(scope, &name, verb, Some(value), learned_by)
+        })
+    }
+
+    pub fn forget(&self, factoid: &Factoid, forgotten_by: &str) -> QueryResult<()> {
+        use schema::factoids;
+
+        self.conn.transaction::<_, QueryError, _>(|| {
+            diesel::delete(factoids::table.find((&factoid.channel[..], &factoid.name[..])))
+                .execute(&self.conn)?;
+            self.record(&factoid.channel, &factoid.name, &factoid.verb, None, forgotten_by)
+        })
+    }
+
+    pub fn set_locked(&self, factoid: &Factoid, locked: bool) -> QueryResult<()> {
+        use schema::factoids;
+
+        diesel::update(factoids::table.find((&factoid.channel[..], &factoid.name[..])))
+            .set(factoids::locked.eq(locked))
+            .execute(&self.conn)?;
+        Ok(())
+    }
+
+    /// Lists the most recent revisions of a factoid in `scope`, newest first.
+    pub fn history(
+        &self, scope: &str, name: &str, limit: i64,
+    ) -> QueryResult<Vec<FactoidRevision>> {
+        use schema::factoid_history;
+
+        factoid_history::table
+            .filter(factoid_history::channel.eq(scope))
+            .filter(factoid_history::name.eq(&normalize(name)[..]))
+            .order(factoid_history::id.desc())
+            .limit(limit)
+            .load(&self.conn)
+    }
+
+    fn record(
+        &self, scope: &str, name: &str, verb: &str, value: Option<&str>, changed_by: &str,
+    ) -> QueryResult<()> {
+        use schema::factoid_history;
+
+        diesel::insert_into(factoid_history::table).values(&NewFactoidRevision {
+            channel: scope,
+            name,
+            verb,
+            value,
+            changed_by,
+            changed: &Utc::now().naive_utc(),
+        }).execute(&self.conn)?;
+        Ok(())
+    }
+}
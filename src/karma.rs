@@ -0,0 +1,137 @@
+use chrono::Utc;
+use diesel;
+use diesel::prelude::*;
+use diesel::result::{Error as QueryError};
+use diesel::sqlite::SqliteConnection;
+
+use models::{KarmaEntry, NewKarmaEntry};
+
+/// Puts the name of a thing in the form its karma is kept under, so that "Rust  Lang" and
+/// "rust lang" are the same thing.
+pub fn normalize(thing: &str) -> String {
+    thing.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+/// Finds every `thing++`, `thing--`, `(multi word thing)++`, and `(multi word thing)--` in
+/// `msg`, along with which way each one changes its karma.
+pub fn votes(msg: &str) -> Vec<(&str, i32)> {
+    let bytes = msg.as_bytes();
+    let mut votes = Vec::new();
+    let mut i = 0;
+    while i + 1 < bytes.len() {
+        let delta = match &bytes[i..i + 2] {
+            b"++" => 1,
+            b"--" => -1,
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+        let (before, after) = (&msg[..i], &msg[i + 2..]);
+        i += 2;
+
+        // "c++11", "a--b", and "+++" aren't votes
+        let next = after.chars().next();
+        if next.map(|c| c.is_alphanumeric() || c == '+' || c == '-').unwrap_or(false) {
+            continue;
+        }
+        let thing = if before.ends_with(')') {
+            match before.rfind('(') {
+                Some(open) => before[open + 1..before.len() - 1].trim(),
+                None => continue,
+            }
+        } else {
+            before.rsplit(char::is_whitespace).next().unwrap_or("")
+                .trim_left_matches(|c: char| !c.is_alphanumeric())
+        };
+        if !thing.is_empty() && !thing.ends_with('+') && !thing.ends_with('-') {
+            votes.push((thing, delta));
+        }
+    }
+    votes
+}
+
+/// The karma of things in each channel.
+pub struct Scores {
+    conn: SqliteConnection,
+}
+
+impl From<SqliteConnection> for Scores {
+    fn from(conn: SqliteConnection) -> Scores {
+        Scores { conn }
+    }
+}
+
+impl Scores {
+    /// Looks up the karma of `thing` in `channel`.
+    pub fn get(&self, channel: &str, thing: &str) -> QueryResult<Option<KarmaEntry>> {
+        use schema::karma;
+
+        karma::table.find((channel, &normalize(thing)[..])).first(&self.conn).optional()
+    }
+
+    /// Changes the karma of `thing` in `channel` by `delta`, returning its new score.
+    pub fn add(&self, channel: &str, thing: &str, delta: i32) -> QueryResult<i32> {
+        use schema::karma;
+
+        let key = normalize(thing);
+        let thing = thing.split_whitespace().collect::<Vec<_>>().join(" ");
+        self.conn.transaction::<_, QueryError, _>(|| {
+            let score = self.get(channel, &key)?.map(|entry| entry.score).unwrap_or(0) + delta;
+            diesel::replace_into(karma::table).values(&NewKarmaEntry {
+                channel,
+                thing_key: &key,
+                thing: &thing,
+                score,
+                updated: &Utc::now().naive_utc(),
+            }).execute(&self.conn)?;
+            Ok(score)
+        })
+    }
+
+    /// Lists the things in `channel` with the most karma, or the least if `best` is false.
+    pub fn ranked(&self, channel: &str, best: bool, limit: i64) -> QueryResult<Vec<KarmaEntry>> {
+        use schema::karma;
+
+        let in_channel = karma::table.filter(karma::channel.eq(channel)).limit(limit);
+        if best {
+            in_channel.order((karma::score.desc(), karma::updated.desc())).load(&self.conn)
+        } else {
+            in_channel.order((karma::score.asc(), karma::updated.desc())).load(&self.conn)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizing() {
+        assert_eq!(normalize("  Rust   Lang "), "rust lang");
+        assert_eq!(normalize("rust"), normalize("RUST"));
+    }
+
+    #[test]
+    fn single_words() {
+        assert_eq!(votes("rust++"), vec![("rust", 1)]);
+        assert_eq!(votes("thanks alice++! and bob--"), vec![("alice", 1), ("bob", -1)]);
+        assert_eq!(votes("@alice++"), vec![("alice", 1)]);
+        assert_eq!(votes("café++"), vec![("café", 1)]);
+    }
+
+    #[test]
+    fn parenthesized_phrases() {
+        assert_eq!(votes("(rust lang)++"), vec![("rust lang", 1)]);
+        assert_eq!(votes("I love ( the borrow checker )--"), vec![("the borrow checker", -1)]);
+        assert!(votes("()++").is_empty());
+        assert!(votes("oops)++").is_empty());
+    }
+
+    #[test]
+    fn things_that_are_not_votes() {
+        for msg in &["c++11", "a--b", "+++", "foo+++", "x ++", "-- signature", "i++j", "--"] {
+            assert!(votes(msg).is_empty(), "{:?} shouldn't be a vote", msg);
+        }
+    }
+}
@@ -11,6 +11,7 @@ extern crate failure;
 #[macro_use]
 extern crate log;
 extern crate irc;
+extern crate rand;
 extern crate toml;
 extern crate tokio_core;
 extern crate tokio_timer;
@@ -18,20 +19,58 @@ extern crate tokio_timer;
 #[macro_use]
 mod dispatch;
 
+mod announce;
 mod app;
+mod args;
+mod backoff;
 mod cmd;
+mod config;
+mod cron;
+mod descriptions;
 mod error;
+mod factoids;
+mod karma;
 mod models;
+mod nick;
+mod people;
+mod perms;
+mod quotes;
+mod remind;
+mod roster;
 mod schema;
+mod when;
 
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::thread;
+
+use backoff::{describe, Backoff};
 use error::*;
 
 fn main() {
     env_logger::init();
 
-    while let Err(err) = app::main_impl() {
+    let backoff = Rc::new(RefCell::new(Backoff::default()));
+    while let Err(err) = app::main_impl(&backoff) {
         match err {
-            Ephemeral(e) => report_err(&e),
+            Ephemeral(e) => {
+                report_err(&e);
+                let delay = backoff.borrow_mut().failed();
+                match delay {
+                    Some(delay) => {
+                        info!("reconnecting in {}", describe(delay));
+                        thread::sleep(delay);
+                    }
+                    None => {
+                        error!(
+                            "giving up after {} failures within {}",
+                            backoff.borrow().max_failures() + 1,
+                            describe(backoff.borrow().window())
+                        );
+                        break;
+                    }
+                }
+            }
             Permanent(e) => {
                 report_err(&e);
                 break;
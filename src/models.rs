@@ -1,49 +1,70 @@
 use std::fmt::{Display, Error, Formatter};
 
 use chrono::{DateTime, NaiveDateTime, Utc};
+use diesel::sql_types::Text;
 
-use schema::{mail, whois};
+use schema::{announcements, factoid_history, factoids, karma, mail, nick_links, quotes,
+             reminders, roles, roster, seen, whois, whois_audit, whois_facts, whois_history,
+             whois_locks};
 
 #[derive(Queryable)]
 pub struct Message {
-    _key: i32,
+    pub id: i32,
     pub target: String,
     pub sender: String,
     pub message: String,
     pub sent: NaiveDateTime,
     pub private: bool,
+    pub deliver_after: Option<NaiveDateTime>,
+    /// Whether the sender asked to be told when this is delivered.
+    pub request_receipt: bool,
+    /// Whether this is itself such a receipt, in which case `message` says what was read where.
+    pub receipt: bool,
+    /// When the message is discarded if it still hasn't been delivered.
+    pub expires: Option<NaiveDateTime>,
+    pub target_key: String,
+    pub sender_key: String,
 }
 
 impl Message {
-    fn time_ago_str(sent: NaiveDateTime) -> String {
-        let sent_utc = DateTime::<Utc>::from_utc(sent, Utc);
-        let dur = Utc::now().signed_duration_since(sent_utc);
-        if dur.num_weeks() > 1 {
-            format!("{} weeks ago", dur.num_weeks())
-        } else if dur.num_weeks() == 1 {
-            "A week ago".to_owned()
-        } else if dur.num_days() > 1 {
-            format!("{} days ago", dur.num_days())
-        } else if dur.num_days() == 1 {
-            "A day ago".to_owned()
-        } else if dur.num_hours() > 1 {
-            format!("{} hours ago", dur.num_hours())
-        } else if dur.num_hours() == 1 {
-            "An hour ago".to_owned()
-        } else if dur.num_minutes() > 1 {
-            format!("{} minutes ago", dur.num_minutes())
-        } else if dur.num_minutes() == 1 {
-            "A minute ago".to_owned()
-        } else {
-            "Moments ago".to_owned()
-        }
+    pub fn is_expired(&self, now: NaiveDateTime) -> bool {
+        self.expires.map(|time| time <= now).unwrap_or(false)
     }
+}
 
+/// Describes how long ago `sent` was, e.g. "3 hours ago" or "Moments ago".
+pub fn time_ago_str(sent: NaiveDateTime) -> String {
+    let sent_utc = DateTime::<Utc>::from_utc(sent, Utc);
+    let dur = Utc::now().signed_duration_since(sent_utc);
+    if dur.num_weeks() > 1 {
+        format!("{} weeks ago", dur.num_weeks())
+    } else if dur.num_weeks() == 1 {
+        "A week ago".to_owned()
+    } else if dur.num_days() > 1 {
+        format!("{} days ago", dur.num_days())
+    } else if dur.num_days() == 1 {
+        "A day ago".to_owned()
+    } else if dur.num_hours() > 1 {
+        format!("{} hours ago", dur.num_hours())
+    } else if dur.num_hours() == 1 {
+        "An hour ago".to_owned()
+    } else if dur.num_minutes() > 1 {
+        format!("{} minutes ago", dur.num_minutes())
+    } else if dur.num_minutes() == 1 {
+        "A minute ago".to_owned()
+    } else {
+        "Moments ago".to_owned()
+    }
 }
 
 impl Display for Message {
     fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
-        let ago = Message::time_ago_str(self.sent);
+        let ago = time_ago_str(self.sent);
+        if self.receipt {
+            return write!(
+                fmt, "{}: {}, {} read your message {}.", self.target, ago, self.sender, self.message
+            );
+        }
         write!(
             fmt, "{}: {}, {} said {}{}", self.target, ago, self.sender,
             self.message,
@@ -61,12 +82,40 @@ pub struct NewMessage<'a> {
     pub message: &'a str,
     pub sent: &'a NaiveDateTime,
     pub private: bool,
+    pub deliver_after: Option<NaiveDateTime>,
+    pub request_receipt: bool,
+    pub receipt: bool,
+    pub expires: Option<NaiveDateTime>,
+    pub target_key: &'a str,
+    pub sender_key: &'a str,
+}
+
+#[derive(Queryable)]
+pub struct NickLink {
+    pub nickname: String,
+    pub person: String,
+    pub account: Option<String>,
+    pub linked: NaiveDateTime,
+    pub nickname_key: String,
+}
+
+#[derive(Insertable)]
+#[table_name="nick_links"]
+pub struct NewNickLink<'a> {
+    pub nickname: &'a str,
+    pub person: &'a str,
+    pub account: Option<&'a str>,
+    pub linked: &'a NaiveDateTime,
+    pub nickname_key: &'a str,
 }
 
 #[derive(Queryable)]
 pub struct WhoisEntry {
     pub nickname: String,
     pub description: String,
+    pub nickname_key: String,
+    /// The services account of whoever wrote the description, if they were identified.
+    pub account: Option<String>,
 }
 
 impl Display for WhoisEntry {
@@ -80,4 +129,420 @@ impl Display for WhoisEntry {
 pub struct NewWhoisEntry<'a> {
     pub nickname: &'a str,
     pub description: &'a str,
+    pub nickname_key: &'a str,
+    pub account: Option<&'a str>,
+}
+
+#[derive(Queryable)]
+pub struct WhoisFact {
+    pub nickname_key: String,
+    pub name: String,
+    pub nickname: String,
+    pub value: String,
+    pub account: Option<String>,
+}
+
+#[derive(Insertable)]
+#[table_name="whois_facts"]
+pub struct NewWhoisFact<'a> {
+    pub nickname_key: &'a str,
+    pub name: &'a str,
+    pub nickname: &'a str,
+    pub value: &'a str,
+    pub account: Option<&'a str>,
+}
+
+/// A description or fact found by a full-text search.
+#[derive(QueryableByName)]
+pub struct WhoisMatch {
+    #[sql_type = "Text"]
+    pub nickname_key: String,
+    #[sql_type = "Text"]
+    pub nickname: String,
+    /// The name of the fact that matched, or empty if it was the description.
+    #[sql_type = "Text"]
+    pub field: String,
+}
+
+#[derive(Queryable)]
+pub struct WhoisRevision {
+    pub id: i32,
+    pub nickname_key: String,
+    pub nickname: String,
+    /// The description as of this revision, or `None` if it was removed.
+    pub description: Option<String>,
+    pub changed_by: String,
+    pub changed: NaiveDateTime,
+}
+
+impl Display for WhoisRevision {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
+        let ago = time_ago_str(self.changed).to_lowercase();
+        match self.description {
+            Some(ref description) => write!(
+                fmt, "#{} by {} {}: {}", self.id, self.changed_by, ago, description
+            ),
+            None => write!(fmt, "#{} by {} {}: (removed)", self.id, self.changed_by, ago),
+        }
+    }
+}
+
+#[derive(Insertable)]
+#[table_name="whois_history"]
+pub struct NewWhoisRevision<'a> {
+    pub nickname_key: &'a str,
+    pub nickname: &'a str,
+    pub description: Option<&'a str>,
+    pub changed_by: &'a str,
+    pub changed: &'a NaiveDateTime,
+}
+
+#[derive(Queryable)]
+pub struct WhoisLock {
+    pub nickname_key: String,
+    pub locked_by: String,
+    pub locked: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name="whois_locks"]
+pub struct NewWhoisLock<'a> {
+    pub nickname_key: &'a str,
+    pub locked_by: &'a str,
+    pub locked: &'a NaiveDateTime,
+}
+
+/// A change an owner made to someone else's description or facts.
+#[derive(Insertable)]
+#[table_name="whois_audit"]
+pub struct NewWhoisAudit<'a> {
+    pub nickname_key: &'a str,
+    pub nickname: &'a str,
+    /// What was done: `forget`, `lock`, `unlock`, `set`, or `revert`.
+    pub action: &'a str,
+    /// What was forgotten, set, or reverted to, where that applies.
+    pub detail: Option<&'a str>,
+    pub changed_by: &'a str,
+    pub changed: &'a NaiveDateTime,
+}
+
+#[derive(Queryable)]
+pub struct RoleBinding {
+    pub mask: String,
+    pub role: String,
+}
+
+#[derive(Insertable)]
+#[table_name="roles"]
+pub struct NewRoleBinding<'a> {
+    pub mask: &'a str,
+    pub role: &'a str,
+}
+
+#[derive(Queryable)]
+pub struct RosterEntry {
+    pub channel: String,
+    pub nickname: String,
+    pub username: Option<String>,
+    pub hostname: Option<String>,
+    pub account: Option<String>,
+    pub away: bool,
+    pub modes: String,
+    pub channel_key: String,
+    pub nickname_key: String,
+}
+
+#[derive(Insertable)]
+#[table_name="roster"]
+pub struct NewRosterEntry<'a> {
+    pub channel: &'a str,
+    pub nickname: &'a str,
+    pub username: Option<&'a str>,
+    pub hostname: Option<&'a str>,
+    pub account: Option<&'a str>,
+    pub away: bool,
+    pub modes: &'a str,
+    pub channel_key: &'a str,
+    pub nickname_key: &'a str,
+}
+
+#[derive(Queryable)]
+pub struct SeenEntry {
+    pub nickname: String,
+    pub action: String,
+    pub channel: Option<String>,
+    pub detail: Option<String>,
+    pub time: NaiveDateTime,
+    pub nickname_key: String,
+}
+
+impl Display for SeenEntry {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
+        let ago = time_ago_str(self.time).to_lowercase();
+        let channel = self.channel.as_ref().map(|s| &s[..]).unwrap_or("somewhere");
+        match (&self.action[..], self.detail.as_ref()) {
+            ("message", Some(detail)) => write!(
+                fmt, "{} was last seen in {} {}, saying: {}", self.nickname, channel, ago, detail
+            ),
+            ("join", _) => write!(
+                fmt, "{} was last seen joining {} {}.", self.nickname, channel, ago
+            ),
+            ("part", Some(reason)) => write!(
+                fmt, "{} was last seen leaving {} {} ({}).", self.nickname, channel, ago, reason
+            ),
+            ("part", None) => write!(
+                fmt, "{} was last seen leaving {} {}.", self.nickname, channel, ago
+            ),
+            ("quit", Some(reason)) => write!(
+                fmt, "{} was last seen quitting {} ({}).", self.nickname, ago, reason
+            ),
+            ("nick", Some(other)) => write!(
+                fmt, "{} was last seen changing nick to {} {}.", self.nickname, other, ago
+            ),
+            ("renamed", Some(other)) => write!(
+                fmt, "{} was last seen changing nick from {} {}.", self.nickname, other, ago
+            ),
+            _ => write!(fmt, "{} was last seen {}.", self.nickname, ago),
+        }
+    }
+}
+
+#[derive(Insertable)]
+#[table_name="seen"]
+pub struct NewSeenEntry<'a> {
+    pub nickname: &'a str,
+    pub action: &'a str,
+    pub channel: Option<&'a str>,
+    pub detail: Option<&'a str>,
+    pub time: &'a NaiveDateTime,
+    pub nickname_key: &'a str,
+}
+
+#[derive(Queryable)]
+pub struct Reminder {
+    pub id: i32,
+    pub creator: String,
+    pub target: String,
+    pub channel: String,
+    pub message: String,
+    pub created: NaiveDateTime,
+    pub due: NaiveDateTime,
+    pub creator_key: String,
+}
+
+impl Reminder {
+    /// Where the reminder should be sent: the target channel, or wherever it was set.
+    pub fn destination(&self) -> &str {
+        if self.target.starts_with('#') || self.target.starts_with('&') {
+            &self.target
+        } else if self.channel == self.creator {
+            // reminders set in a query are sent privately to whoever they're for
+            &self.target
+        } else {
+            &self.channel
+        }
+    }
+}
+
+impl Display for Reminder {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
+        if self.target.starts_with('#') || self.target.starts_with('&') {
+            write!(fmt, "Reminder from {}: {}", self.creator, self.message)
+        } else if self.target == self.creator {
+            write!(fmt, "{}: you asked me to remind you: {}", self.target, self.message)
+        } else {
+            write!(
+                fmt, "{}: {} asked me to remind you: {}", self.target, self.creator, self.message
+            )
+        }
+    }
+}
+
+#[derive(Insertable)]
+#[table_name="reminders"]
+pub struct NewReminder<'a> {
+    pub creator: &'a str,
+    pub target: &'a str,
+    pub channel: &'a str,
+    pub message: &'a str,
+    pub created: &'a NaiveDateTime,
+    pub due: &'a NaiveDateTime,
+    pub creator_key: &'a str,
+}
+
+#[derive(Queryable)]
+pub struct Announcement {
+    pub id: i32,
+    pub creator: String,
+    pub channel: String,
+    pub schedule: String,
+    pub message: String,
+    pub next_run: NaiveDateTime,
+    pub creator_key: String,
+}
+
+#[derive(Insertable)]
+#[table_name="announcements"]
+pub struct NewAnnouncement<'a> {
+    pub creator: &'a str,
+    pub channel: &'a str,
+    pub schedule: &'a str,
+    pub message: &'a str,
+    pub next_run: &'a NaiveDateTime,
+    pub creator_key: &'a str,
+}
+
+#[derive(Queryable)]
+pub struct Factoid {
+    /// The (folded) channel the factoid belongs to, or empty if it applies everywhere.
+    pub channel: String,
+    pub name: String,
+    /// How the name and value are joined, i.e. "is" or "are".
+    pub verb: String,
+    pub value: String,
+    pub created_by: String,
+    pub created: NaiveDateTime,
+    /// Whether only owners can change or forget it.
+    pub locked: bool,
+}
+
+/// What to do when someone asks about a factoid.
+pub enum FactoidReply {
+    Say(String),
+    Act(String),
+}
+
+impl Factoid {
+    /// Works out the reply to `nick` asking in `channel`. Values starting with `<reply>` are said
+    /// as they are and those starting with `<action>` are acted out, rather than being read out
+    /// as "name is value", and `$nick` and `$channel` are filled in.
+    pub fn reply(&self, nick: &str, channel: &str) -> FactoidReply {
+        let value = self.value.replace("$channel", channel).replace("$nick", nick);
+        let tagged = |tag: &str| match value.get(..tag.len()) {
+            Some(start) if start.eq_ignore_ascii_case(tag) => {
+                Some(value[tag.len()..].trim().to_owned())
+            }
+            _ => None,
+        };
+
+        if let Some(text) = tagged("<reply>") {
+            FactoidReply::Say(text)
+        } else if let Some(text) = tagged("<action>") {
+            FactoidReply::Act(text)
+        } else {
+            FactoidReply::Say(format!("{} {} {}", self.name, self.verb, value))
+        }
+    }
+}
+
+#[derive(Insertable)]
+#[table_name="factoids"]
+pub struct NewFactoid<'a> {
+    pub channel: &'a str,
+    pub name: &'a str,
+    pub verb: &'a str,
+    pub value: &'a str,
+    pub created_by: &'a str,
+    pub created: &'a NaiveDateTime,
+    pub locked: bool,
+}
+
+#[derive(Queryable)]
+pub struct FactoidRevision {
+    pub id: i32,
+    pub channel: String,
+    pub name: String,
+    pub verb: String,
+    /// The value as of this revision, or `None` if it was forgotten.
+    pub value: Option<String>,
+    pub changed_by: String,
+    pub changed: NaiveDateTime,
+}
+
+impl Display for FactoidRevision {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
+        let ago = time_ago_str(self.changed).to_lowercase();
+        match self.value {
+            Some(ref value) => write!(
+                fmt, "#{} by {} {}: {} {} {}", self.id, self.changed_by, ago, self.name, self.verb,
+                value
+            ),
+            None => write!(fmt, "#{} by {} {}: (forgotten)", self.id, self.changed_by, ago),
+        }
+    }
+}
+
+#[derive(Insertable)]
+#[table_name="factoid_history"]
+pub struct NewFactoidRevision<'a> {
+    pub channel: &'a str,
+    pub name: &'a str,
+    pub verb: &'a str,
+    pub value: Option<&'a str>,
+    pub changed_by: &'a str,
+    pub changed: &'a NaiveDateTime,
+}
+
+#[derive(Queryable, QueryableByName)]
+#[table_name="quotes"]
+pub struct Quote {
+    pub id: i32,
+    /// The (folded) channel it was added in, or empty if it was added in a query.
+    pub channel: String,
+    /// Who said it, if it was grabbed rather than written out.
+    pub nickname: Option<String>,
+    pub text: String,
+    pub added_by: String,
+    pub added: NaiveDateTime,
+}
+
+impl Display for Quote {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
+        let ago = time_ago_str(self.added).to_lowercase();
+        match self.nickname {
+            Some(ref nickname) => write!(fmt, "#{}: <{}> {}", self.id, nickname, self.text)?,
+            None => write!(fmt, "#{}: {}", self.id, self.text)?,
+        }
+        if self.channel.is_empty() {
+            write!(fmt, " (added by {} {})", self.added_by, ago)
+        } else {
+            write!(fmt, " (added by {} in {} {})", self.added_by, self.channel, ago)
+        }
+    }
+}
+
+#[derive(Insertable)]
+#[table_name="quotes"]
+pub struct NewQuote<'a> {
+    pub channel: &'a str,
+    pub nickname: Option<&'a str>,
+    pub text: &'a str,
+    pub added_by: &'a str,
+    pub added: &'a NaiveDateTime,
+}
+
+#[derive(Queryable)]
+pub struct KarmaEntry {
+    /// The (folded) channel it was given in.
+    pub channel: String,
+    pub thing_key: String,
+    pub thing: String,
+    pub score: i32,
+    pub updated: NaiveDateTime,
+}
+
+impl Display for KarmaEntry {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
+        write!(fmt, "{} ({})", self.thing, self.score)
+    }
+}
+
+#[derive(Insertable)]
+#[table_name="karma"]
+pub struct NewKarmaEntry<'a> {
+    pub channel: &'a str,
+    pub thing_key: &'a str,
+    pub thing: &'a str,
+    pub score: i32,
+    pub updated: &'a NaiveDateTime,
 }
@@ -0,0 +1,476 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Error, Formatter};
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use diesel;
+use diesel::prelude::*;
+use diesel::result::{Error as QueryError};
+use diesel::sqlite::SqliteConnection;
+use irc::client::prelude::*;
+
+/// How the server decides that two nicknames or channel names are the same, as advertised by the
+/// `CASEMAPPING` token in `RPL_ISUPPORT`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaseMapping {
+    /// Only `A` to `Z` are folded.
+    Ascii,
+    /// Like `Ascii`, but `[]\~` are also the uppercase forms of `{}|^`. This is the default.
+    Rfc1459,
+    /// Like `Rfc1459`, but without `~` and `^`.
+    StrictRfc1459,
+}
+
+impl CaseMapping {
+    /// Folds `name` to the form that is the same for every spelling the server considers equal.
+    pub fn fold(self, name: &str) -> String {
+        name.chars().map(|c| match (self, c) {
+            (_, 'A'...'Z') => c.to_ascii_lowercase(),
+            (CaseMapping::Ascii, _) => c,
+            (_, '[') => '{',
+            (_, ']') => '}',
+            (_, '\\') => '|',
+            (CaseMapping::Rfc1459, '~') => '^',
+            _ => c,
+        }).collect()
+    }
+
+    /// The name of the casemapping as it's written in `RPL_ISUPPORT`.
+    pub fn name(self) -> &'static str {
+        match self {
+            CaseMapping::Ascii => "ascii",
+            CaseMapping::Rfc1459 => "rfc1459",
+            CaseMapping::StrictRfc1459 => "strict-rfc1459",
+        }
+    }
+}
+
+impl Default for CaseMapping {
+    fn default() -> CaseMapping {
+        CaseMapping::Rfc1459
+    }
+}
+
+impl FromStr for CaseMapping {
+    type Err = UnknownCaseMapping;
+
+    fn from_str(s: &str) -> Result<CaseMapping, UnknownCaseMapping> {
+        match &s.to_lowercase()[..] {
+            "ascii" => Ok(CaseMapping::Ascii),
+            "rfc1459" => Ok(CaseMapping::Rfc1459),
+            "strict-rfc1459" => Ok(CaseMapping::StrictRfc1459),
+            _ => Err(UnknownCaseMapping(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug, Fail)]
+#[fail(display = "unsupported casemapping: {}", _0)]
+pub struct UnknownCaseMapping(pub String);
+
+/// The casemapping of the server we're connected to. Everything runs on the reactor's thread,
+/// but it's kept in an atomic so that folding a name never needs any state passed around.
+static CASEMAPPING: AtomicUsize = AtomicUsize::new(0);
+
+pub fn casemapping() -> CaseMapping {
+    match CASEMAPPING.load(Ordering::Relaxed) {
+        1 => CaseMapping::Ascii,
+        2 => CaseMapping::StrictRfc1459,
+        _ => CaseMapping::Rfc1459,
+    }
+}
+
+/// Switches to `mapping`, returning whether it was different from before.
+pub fn set_casemapping(mapping: CaseMapping) -> bool {
+    let value = match mapping {
+        CaseMapping::Rfc1459 => 0,
+        CaseMapping::Ascii => 1,
+        CaseMapping::StrictRfc1459 => 2,
+    };
+    CASEMAPPING.swap(value, Ordering::Relaxed) != value
+}
+
+/// Reads the casemapping from an `RPL_ISUPPORT` message, if that's what it is and it has one.
+pub fn casemapping_of(message: &Message) -> Option<CaseMapping> {
+    let args = match message.command {
+        Command::Response(Response::RPL_ISUPPORT, ref args, _) => args,
+        _ => return None,
+    };
+    let value = args.iter().find(|arg| arg.starts_with("CASEMAPPING="))?;
+    match value["CASEMAPPING=".len()..].parse() {
+        Ok(mapping) => Some(mapping),
+        Err(e) => {
+            warn!("{}, falling back to rfc1459", e);
+            Some(CaseMapping::Rfc1459)
+        }
+    }
+}
+
+/// Folds `name` using the server's casemapping.
+pub fn fold(name: &str) -> String {
+    casemapping().fold(name)
+}
+
+/// Checks whether two nicknames or channel names are the same to the server.
+pub fn eq(a: &str, b: &str) -> bool {
+    a.len() == b.len() && fold(a) == fold(b)
+}
+
+/// A nickname or channel name that compares and hashes the way the server does, while keeping
+/// the spelling it was given for display.
+#[derive(Clone, Debug)]
+pub struct Nickname {
+    name: String,
+    folded: String,
+}
+
+impl Nickname {
+    /// Folds the name again, after the casemapping has changed.
+    pub fn refold(&mut self) {
+        self.folded = fold(&self.name);
+    }
+}
+
+impl<'a> From<&'a str> for Nickname {
+    fn from(name: &'a str) -> Nickname {
+        Nickname { name: name.to_owned(), folded: fold(name) }
+    }
+}
+
+impl From<String> for Nickname {
+    fn from(name: String) -> Nickname {
+        let folded = fold(&name);
+        Nickname { name, folded }
+    }
+}
+
+impl Deref for Nickname {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.name
+    }
+}
+
+impl PartialEq for Nickname {
+    fn eq(&self, other: &Nickname) -> bool {
+        self.folded == other.folded
+    }
+}
+
+impl Eq for Nickname {}
+
+impl Hash for Nickname {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.folded.hash(state);
+    }
+}
+
+impl Display for Nickname {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
+        write!(fmt, "{}", self.name)
+    }
+}
+
+/// Maps keys folded with one casemapping to the same names folded with another. A key can't be
+/// unfolded, so this learns what each one becomes from the spellings of nicknames, accounts, and
+/// channels on record.
+struct Refold {
+    from: CaseMapping,
+    to: CaseMapping,
+    known: HashMap<String, String>,
+}
+
+impl Refold {
+    fn new(from: CaseMapping, to: CaseMapping) -> Refold {
+        Refold { from, to, known: HashMap::new() }
+    }
+
+    /// Learns what the key of `name` becomes. When several spellings folded together before but
+    /// no longer do, the first one learned wins.
+    fn learn(&mut self, name: &str) {
+        let (from, to) = (self.from, self.to);
+        self.known.entry(from.fold(name)).or_insert_with(|| to.fold(name));
+    }
+
+    /// Finds what `key` becomes, going by `name` if that's what it was folded from. Keys that
+    /// were never learned are left as they are.
+    fn key(&self, key: &str, name: Option<&str>) -> String {
+        match name {
+            Some(name) if self.from.fold(name) == key => self.to.fold(name),
+            _ => self.known.get(key).cloned().unwrap_or_else(|| key.to_owned()),
+        }
+    }
+}
+
+/// Recomputes every `_key` column, the people that nicknames are grouped into, and the channels
+/// that factoids, quotes, and karma belong to, for the casemapping `to`. The database records
+/// which casemapping it was keyed for, so nothing is touched unless that has changed. Returns
+/// whether anything was rekeyed.
+pub fn rekey(conn: &SqliteConnection, to: CaseMapping) -> QueryResult<bool> {
+    use models::{Factoid, KarmaEntry, NewFactoid, NewKarmaEntry, NewNickLink, NewRosterEntry,
+                 NewSeenEntry, NewWhoisEntry, NewWhoisFact, NewWhoisLock, NickLink, RosterEntry,
+                 SeenEntry, WhoisEntry, WhoisFact, WhoisLock};
+    use schema::{announcements, casemapping, factoid_history, factoids, karma, mail, nick_links,
+                 quotes, reminders, roster, seen, whois, whois_audit, whois_facts, whois_history,
+                 whois_locks};
+
+    conn.transaction::<_, QueryError, _>(|| {
+        let stored = casemapping::table.select(casemapping::mapping).first::<String>(conn)?;
+        let from = match stored.parse() {
+            Ok(from) => from,
+            Err(e) => {
+                warn!("{}, assuming keys were folded with rfc1459", e);
+                CaseMapping::Rfc1459
+            }
+        };
+        if from == to {
+            return Ok(false);
+        }
+
+        // people are usually named after one of their nicknames, so those are learned first
+        let mut refold = Refold::new(from, to);
+        let links = nick_links::table.order(nick_links::linked).load::<NickLink>(conn)?;
+        for link in &links {
+            refold.learn(&link.nickname);
+            if let Some(ref account) = link.account {
+                refold.learn(&format!("$a:{}", account));
+            }
+        }
+        let mut names = Vec::new();
+        names.extend(whois::table.select(whois::nickname).load::<String>(conn)?);
+        names.extend(whois_facts::table.select(whois_facts::nickname).load::<String>(conn)?);
+        names.extend(whois_history::table.select(whois_history::nickname).load::<String>(conn)?);
+        names.extend(whois_audit::table.select(whois_audit::nickname).load::<String>(conn)?);
+        names.extend(seen::table.select(seen::nickname).load::<String>(conn)?);
+        // and channels, for the factoids, quotes, and karma that belong to them
+        names.extend(
+            seen::table.select(seen::channel).load::<Option<String>>(conn)?
+                .into_iter()
+                .filter_map(|channel| channel)
+        );
+        names.extend(roster::table.select(roster::channel).load::<String>(conn)?);
+        names.extend(reminders::table.select(reminders::channel).load::<String>(conn)?);
+        names.extend(announcements::table.select(announcements::channel).load::<String>(conn)?);
+        for name in &names {
+            refold.learn(name);
+        }
+
+        for (id, target, sender) in mail::table
+            .select((mail::id, mail::target, mail::sender))
+            .load::<(i32, String, String)>(conn)? {
+            diesel::update(mail::table.find(id))
+                .set((mail::target_key.eq(to.fold(&target)), mail::sender_key.eq(to.fold(&sender))))
+                .execute(conn)?;
+        }
+        for (id, creator) in reminders::table
+            .select((reminders::id, reminders::creator))
+            .load::<(i32, String)>(conn)? {
+            diesel::update(reminders::table.find(id))
+                .set(reminders::creator_key.eq(to.fold(&creator)))
+                .execute(conn)?;
+        }
+        for (id, creator) in announcements::table
+            .select((announcements::id, announcements::creator))
+            .load::<(i32, String)>(conn)? {
+            diesel::update(announcements::table.find(id))
+                .set(announcements::creator_key.eq(to.fold(&creator)))
+                .execute(conn)?;
+        }
+
+        // the rest are keyed by nickname, so spellings that now fold together are merged, and
+        // descriptions are keyed by the person they belong to, which may be named after another
+        // nickname entirely
+        let entries = whois::table.load::<WhoisEntry>(conn)?;
+        diesel::delete(whois::table).execute(conn)?;
+        for entry in &entries {
+            diesel::replace_into(whois::table).values(&NewWhoisEntry {
+                nickname: &entry.nickname,
+                description: &entry.description,
+                nickname_key: &refold.key(&entry.nickname_key, Some(&entry.nickname)),
+                account: entry.account.as_ref().map(|s| &s[..]),
+            }).execute(conn)?;
+        }
+
+        let facts = whois_facts::table.load::<WhoisFact>(conn)?;
+        diesel::delete(whois_facts::table).execute(conn)?;
+        for fact in &facts {
+            diesel::replace_into(whois_facts::table).values(&NewWhoisFact {
+                nickname_key: &refold.key(&fact.nickname_key, Some(&fact.nickname)),
+                name: &fact.name,
+                nickname: &fact.nickname,
+                value: &fact.value,
+                account: fact.account.as_ref().map(|s| &s[..]),
+            }).execute(conn)?;
+        }
+
+        let locks = whois_locks::table.load::<WhoisLock>(conn)?;
+        diesel::delete(whois_locks::table).execute(conn)?;
+        for lock in &locks {
+            diesel::replace_into(whois_locks::table).values(&NewWhoisLock {
+                nickname_key: &refold.key(&lock.nickname_key, None),
+                locked_by: &lock.locked_by,
+                locked: &lock.locked,
+            }).execute(conn)?;
+        }
+
+        for (id, key, nickname) in whois_history::table
+            .select((whois_history::id, whois_history::nickname_key, whois_history::nickname))
+            .load::<(i32, String, String)>(conn)? {
+            diesel::update(whois_history::table.find(id))
+                .set(whois_history::nickname_key.eq(refold.key(&key, Some(&nickname))))
+                .execute(conn)?;
+        }
+        for (id, key, nickname) in whois_audit::table
+            .select((whois_audit::id, whois_audit::nickname_key, whois_audit::nickname))
+            .load::<(i32, String, String)>(conn)? {
+            diesel::update(whois_audit::table.find(id))
+                .set(whois_audit::nickname_key.eq(refold.key(&key, Some(&nickname))))
+                .execute(conn)?;
+        }
+
+        let entries = seen::table.order(seen::time).load::<SeenEntry>(conn)?;
+        diesel::delete(seen::table).execute(conn)?;
+        for entry in &entries {
+            diesel::replace_into(seen::table).values(&NewSeenEntry {
+                nickname: &entry.nickname,
+                action: &entry.action,
+                channel: entry.channel.as_ref().map(|s| &s[..]),
+                detail: entry.detail.as_ref().map(|s| &s[..]),
+                time: &entry.time,
+                nickname_key: &to.fold(&entry.nickname),
+            }).execute(conn)?;
+        }
+
+        let members = roster::table.load::<RosterEntry>(conn)?;
+        diesel::delete(roster::table).execute(conn)?;
+        for member in &members {
+            diesel::replace_into(roster::table).values(&NewRosterEntry {
+                channel: &member.channel,
+                nickname: &member.nickname,
+                username: member.username.as_ref().map(|s| &s[..]),
+                hostname: member.hostname.as_ref().map(|s| &s[..]),
+                account: member.account.as_ref().map(|s| &s[..]),
+                away: member.away,
+                modes: &member.modes,
+                channel_key: &to.fold(&member.channel),
+                nickname_key: &to.fold(&member.nickname),
+            }).execute(conn)?;
+        }
+
+        diesel::delete(nick_links::table).execute(conn)?;
+        for link in &links {
+            diesel::replace_into(nick_links::table).values(&NewNickLink {
+                nickname: &link.nickname,
+                person: &refold.key(&link.person, Some(&link.nickname)),
+                account: link.account.as_ref().map(|s| &s[..]),
+                linked: &link.linked,
+                nickname_key: &to.fold(&link.nickname),
+            }).execute(conn)?;
+        }
+
+        // factoids, quotes, and karma are scoped by channel, which is folded too
+        let learned = factoids::table.load::<Factoid>(conn)?;
+        diesel::delete(factoids::table).execute(conn)?;
+        for factoid in &learned {
+            diesel::replace_into(factoids::table).values(&NewFactoid {
+                channel: &refold.key(&factoid.channel, None),
+                name: &factoid.name,
+                verb: &factoid.verb,
+                value: &factoid.value,
+                created_by: &factoid.created_by,
+                created: &factoid.created,
+                locked: factoid.locked,
+            }).execute(conn)?;
+        }
+        for (id, channel) in factoid_history::table
+            .select((factoid_history::id, factoid_history::channel))
+            .load::<(i32, String)>(conn)? {
+            diesel::update(factoid_history::table.find(id))
+                .set(factoid_history::channel.eq(refold.key(&channel, None)))
+                .execute(conn)?;
+        }
+        for (id, channel) in quotes::table
+            .select((quotes::id, quotes::channel))
+            .load::<(i32, String)>(conn)? {
+            diesel::update(quotes::table.find(id))
+                .set(quotes::channel.eq(refold.key(&channel, None)))
+                .execute(conn)?;
+        }
+
+        // karma in channels that now fold together is added up, under the latest spelling
+        let mut merged: HashMap<(String, String), KarmaEntry> = HashMap::new();
+        for entry in karma::table.order(karma::updated).load::<KarmaEntry>(conn)? {
+            let key = (refold.key(&entry.channel, None), entry.thing_key.clone());
+            let score = merged.get(&key).map(|earlier| earlier.score).unwrap_or(0) + entry.score;
+            merged.insert(key, KarmaEntry { score, .. entry });
+        }
+        diesel::delete(karma::table).execute(conn)?;
+        for (&(ref channel, _), entry) in &merged {
+            diesel::insert_into(karma::table).values(&NewKarmaEntry {
+                channel,
+                thing_key: &entry.thing_key,
+                thing: &entry.thing,
+                score: entry.score,
+                updated: &entry.updated,
+            }).execute(conn)?;
+        }
+
+        diesel::update(casemapping::table)
+            .set(casemapping::mapping.eq(to.name()))
+            .execute(conn)?;
+        Ok(true)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folding() {
+        assert_eq!(CaseMapping::Ascii.fold("Nick[]\\~"), "nick[]\\~");
+        assert_eq!(CaseMapping::Rfc1459.fold("Nick[]\\~"), "nick{}|^");
+        assert_eq!(CaseMapping::StrictRfc1459.fold("Nick[]\\~"), "nick{}|~");
+        // only ASCII letters have cases, as far as IRC is concerned
+        assert_eq!(CaseMapping::Rfc1459.fold("ÉLAN"), "Élan");
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for &mapping in &[CaseMapping::Ascii, CaseMapping::Rfc1459, CaseMapping::StrictRfc1459] {
+            assert_eq!(mapping.name().parse::<CaseMapping>().ok(), Some(mapping));
+        }
+        assert_eq!("RFC1459".parse::<CaseMapping>().ok(), Some(CaseMapping::Rfc1459));
+        assert!("rfc7613".parse::<CaseMapping>().is_err());
+    }
+
+    #[test]
+    fn refolding_starts_from_the_original_spelling() {
+        let mut refold = Refold::new(CaseMapping::Rfc1459, CaseMapping::Ascii);
+        refold.learn("Alice[m]");
+        refold.learn("$a:Bob[x]");
+
+        // folding "alice{m}" again would leave it as it is, which ascii never produces for her
+        assert_eq!(refold.key("alice{m}", Some("Alice[m]")), "alice[m]");
+        assert_eq!(refold.key("alice{m}", None), "alice[m]");
+        assert_eq!(refold.key("$a:bob{x}", None), "$a:bob[x]");
+        // a person named after another of their nicknames
+        assert_eq!(refold.key("alice{m}", Some("alice_away")), "alice[m]");
+        // keys nothing is known about are left alone, rather than mangled
+        assert_eq!(refold.key("carol{}", None), "carol{}");
+        assert_eq!(refold.key("", None), "");
+    }
+
+    #[test]
+    fn refolding_to_rfc1459_merges_spellings() {
+        let mut refold = Refold::new(CaseMapping::Ascii, CaseMapping::Rfc1459);
+        refold.learn("Dave[");
+        refold.learn("dave{");
+        assert_eq!(refold.key("dave[", Some("Dave[")), "dave{");
+        assert_eq!(refold.key("dave{", None), "dave{");
+        assert_eq!(refold.key("dave[", None), "dave{");
+    }
+}
@@ -0,0 +1,181 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use chrono::Utc;
+use diesel;
+use diesel::prelude::*;
+use diesel::result::{Error as QueryError};
+use diesel::sqlite::SqliteConnection;
+
+use models::{NewNickLink, NickLink};
+use nick;
+
+/// Groups nicknames that belong to the same person, like `alice`, `alice_`, and `alice|away`, so
+/// that mail and descriptions follow them from one to another. Each group is named after the
+/// folded form of one of its nicknames (or `$a:account` if that's taken), and a nickname outside
+/// of any group is a person of its own.
+pub struct People {
+    conn: SqliteConnection,
+    /// The account each nickname was last seen using, so that linking by account only touches
+    /// the database when something has changed.
+    accounts: RefCell<HashMap<String, String>>,
+}
+
+impl From<SqliteConnection> for People {
+    fn from(conn: SqliteConnection) -> People {
+        People { conn, accounts: RefCell::new(HashMap::new()) }
+    }
+}
+
+impl People {
+    /// Finds the person that `nick` belongs to.
+    pub fn person_of(&self, nick: &str) -> QueryResult<String> {
+        use schema::nick_links::dsl::*;
+
+        let found = nick_links
+            .find(nick::fold(nick))
+            .select(person)
+            .first::<String>(&self.conn)
+            .optional()?;
+        Ok(found.unwrap_or_else(|| nick::fold(nick)))
+    }
+
+    /// Lists every nickname of the person that `nick` belongs to, starting with `nick` itself.
+    pub fn nicks_of(&self, nick: &str) -> QueryResult<Vec<String>> {
+        use schema::nick_links::dsl::*;
+
+        let group = self.person_of(nick)?;
+        let mut nicks = vec![nick.to_owned()];
+        for other in nick_links
+            .filter(person.eq(&group[..]))
+            .order(nickname)
+            .select(nickname)
+            .load::<String>(&self.conn)? {
+            if !nick::eq(&other, nick) {
+                nicks.push(other);
+            }
+        }
+        Ok(nicks)
+    }
+
+    /// Finds the services account `nick` was last seen using, if any.
+    pub fn account_of(&self, nick: &str) -> QueryResult<Option<String>> {
+        use schema::nick_links::dsl::*;
+
+        let found = nick_links
+            .find(nick::fold(nick))
+            .select(account)
+            .first::<Option<String>>(&self.conn)
+            .optional()?;
+        Ok(found.and_then(|found| found))
+    }
+
+    /// Puts `nick`, along with everyone already grouped with it, in the same group as `other`.
+    /// Returns the name of the group.
+    pub fn link(&self, nick: &str, other: &str) -> QueryResult<String> {
+        use schema::nick_links::dsl::*;
+
+        self.conn.transaction::<_, QueryError, _>(|| {
+            let group = self.person_of(other)?;
+            let old_group = self.person_of(nick)?;
+            let now = Utc::now().naive_utc();
+
+            diesel::update(nick_links.filter(person.eq(&old_group[..])))
+                .set(person.eq(&group[..]))
+                .execute(&self.conn)?;
+            for &name in &[nick, other] {
+                let key = nick::fold(name);
+                if nick_links.find(&key[..]).first::<NickLink>(&self.conn).optional()?.is_none() {
+                    diesel::insert_into(nick_links).values(&NewNickLink {
+                        nickname: name,
+                        person: &group,
+                        account: None,
+                        linked: &now,
+                        nickname_key: &key,
+                    }).execute(&self.conn)?;
+                }
+            }
+
+            Ok(group)
+        })
+    }
+
+    /// Takes `nick` out of its group, returning whether it was in one.
+    pub fn unlink(&self, nick: &str) -> QueryResult<bool> {
+        use schema::nick_links::dsl::*;
+
+        let key = nick::fold(nick);
+        // the account goes with the link, so it has to be recorded again the next time it's seen
+        self.accounts.borrow_mut().remove(&key);
+        self.conn.transaction::<_, QueryError, _>(|| {
+            let link = match nick_links.find(&key[..]).first::<NickLink>(&self.conn).optional()? {
+                Some(link) => link,
+                None => return Ok(false),
+            };
+            diesel::delete(nick_links.find(&key[..])).execute(&self.conn)?;
+
+            // a group named after this nickname is renamed after one of the others, so that the
+            // nickname is free to be a person of its own
+            if link.person == key {
+                let heir = nick_links
+                    .filter(person.eq(&key[..]))
+                    .order(linked)
+                    .select(nickname_key)
+                    .first::<String>(&self.conn)
+                    .optional()?;
+                if let Some(heir) = heir {
+                    diesel::update(nick_links.filter(person.eq(&key[..])))
+                        .set(person.eq(&heir[..]))
+                        .execute(&self.conn)?;
+                }
+            }
+
+            Ok(true)
+        })
+    }
+
+    /// Notes that `nick` is identified to `account`, grouping it with every other nickname that
+    /// has been seen using the same account.
+    pub fn saw_account(&self, nick: &str, account_name: &str) -> QueryResult<()> {
+        use schema::nick_links::dsl::*;
+
+        let key = nick::fold(nick);
+        let same_account = |known: &String| nick::eq(known, account_name);
+        if self.accounts.borrow().get(&key).map(&same_account).unwrap_or(false) {
+            return Ok(());
+        }
+
+        self.conn.transaction::<_, QueryError, _>(|| {
+            let existing = nick_links
+                .filter(account.eq(account_name))
+                .filter(nickname_key.ne(&key[..]))
+                .select(person)
+                .first::<String>(&self.conn)
+                .optional()?;
+            let current = nick_links.find(&key[..]).first::<NickLink>(&self.conn).optional()?;
+
+            let group = match (existing, current) {
+                (Some(group), _) => group,
+                (None, Some(ref link))
+                    if link.account.as_ref().map(&same_account).unwrap_or(true) => {
+                    link.person.clone()
+                }
+                // someone else's nickname, so its group name is taken
+                (None, Some(_)) => format!("$a:{}", nick::fold(account_name)),
+                (None, None) => key.clone(),
+            };
+
+            diesel::replace_into(nick_links).values(&NewNickLink {
+                nickname: nick,
+                person: &group,
+                account: Some(account_name),
+                linked: &Utc::now().naive_utc(),
+                nickname_key: &key,
+            }).execute(&self.conn)?;
+            Ok(())
+        })?;
+
+        self.accounts.borrow_mut().insert(key, account_name.to_owned());
+        Ok(())
+    }
+}
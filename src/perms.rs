@@ -0,0 +1,214 @@
+use std::fmt::{Display, Error, Formatter};
+use std::str::FromStr;
+
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use irc::client::prelude::*;
+use irc::error::Result;
+use irc::error::IrcError::Custom;
+
+use nick;
+
+/// The level of trust required to use a command, ordered from least to most privileged.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Role {
+    Everyone,
+    Trusted,
+    Admin,
+    Owner,
+}
+
+impl FromStr for Role {
+    type Err = UnknownRole;
+
+    fn from_str(s: &str) -> ::std::result::Result<Role, UnknownRole> {
+        match &s.to_lowercase()[..] {
+            "everyone" => Ok(Role::Everyone),
+            "trusted" => Ok(Role::Trusted),
+            "admin" => Ok(Role::Admin),
+            "owner" => Ok(Role::Owner),
+            _ => Err(UnknownRole(s.to_owned())),
+        }
+    }
+}
+
+impl Display for Role {
+    fn fmt(&self, fmt: &mut Formatter) -> ::std::result::Result<(), Error> {
+        write!(fmt, "{}", match *self {
+            Role::Everyone => "everyone",
+            Role::Trusted => "trusted",
+            Role::Admin => "admin",
+            Role::Owner => "owner",
+        })
+    }
+}
+
+#[derive(Debug, Fail)]
+#[fail(display = "unknown role: {} (try owner, admin, trusted, or everyone)", _0)]
+pub struct UnknownRole(pub String);
+
+/// Something a role can be bound to. Masks written as `$a:name` match the NickServ account
+/// `name`, and anything else is a `nick!user@host` glob where `*` and `?` are wildcards.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Mask {
+    Account(String),
+    Host(String),
+}
+
+impl<'a> From<&'a str> for Mask {
+    fn from(mask: &'a str) -> Mask {
+        if mask.starts_with("$a:") {
+            Mask::Account(mask[3..].to_owned())
+        } else {
+            Mask::Host(mask.to_owned())
+        }
+    }
+}
+
+impl Mask {
+    pub fn matches(&self, hostmask: Option<&str>, account: Option<&str>) -> bool {
+        match (self, hostmask, account) {
+            (&Mask::Account(ref name), _, Some(account)) => nick::eq(name, account),
+            (&Mask::Host(ref glob), Some(hostmask), _) => glob_matches(glob, hostmask),
+            _ => false,
+        }
+    }
+}
+
+/// Matches `text` against a glob pattern where `*` matches any sequence of characters and `?`
+/// matches exactly one. Case is ignored according to the server's casemapping.
+pub fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<_> = nick::fold(pattern).chars().collect();
+    let text: Vec<_> = nick::fold(text).chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            backtrack = Some((star, matched + 1));
+            p = star + 1;
+            t = matched + 1;
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Finds the services account a message was sent from, using the IRCv3 `account` tag.
+pub fn account_of(message: &Message) -> Option<&str> {
+    message.tags.as_ref()?.iter().find(|tag| tag.0 == "account").and_then(|tag| {
+        tag.1.as_ref().map(|s| &s[..])
+    })
+}
+
+/// Decides which role a user has from the masks bound in the configuration and in the database.
+pub struct Permissions {
+    conn: SqliteConnection,
+    bindings: Vec<(Mask, Role)>,
+}
+
+impl Permissions {
+    /// Reads bindings from the `roles_owner`, `roles_admin`, and `roles_trusted` options, each a
+    /// comma-separated list of masks. Entries in `owners` are also accepted, with bare nicknames
+    /// treated as account names so that changing nick is not enough to gain access.
+    pub fn new(config: &Config, conn: SqliteConnection) -> Permissions {
+        let mut bindings = Vec::new();
+
+        for owner in config.owners.as_ref().map(|v| &v[..]).unwrap_or(&[]) {
+            if owner.contains('!') || owner.contains('@') || owner.starts_with('$') {
+                bindings.push((Mask::from(&owner[..]), Role::Owner));
+            } else {
+                warn!("owner {} must identify with services to be recognized as an owner", owner);
+                bindings.push((Mask::Account(owner.to_owned()), Role::Owner));
+            }
+        }
+
+        for &(option, role) in &[
+            ("roles_owner", Role::Owner),
+            ("roles_admin", Role::Admin),
+            ("roles_trusted", Role::Trusted),
+        ] {
+            let masks = config.get_option(option).unwrap_or("");
+            for mask in masks.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+                bindings.push((Mask::from(mask), role));
+            }
+        }
+
+        Permissions { conn, bindings }
+    }
+
+    /// Finds the most privileged role bound to the sender of `message`, who is identified to
+    /// `account` if that's known.
+    pub fn role_of(&self, message: &Message, account: Option<&str>) -> Result<Role> {
+        use models::RoleBinding;
+        use schema::roles::dsl::*;
+
+        let hostmask = message.prefix.as_ref().map(|s| &s[..]);
+
+        let stored = roles.load::<RoleBinding>(&self.conn).map_err(|e| Custom { inner: e.into() })?;
+        let stored = stored.iter().filter_map(|binding| {
+            match binding.role.parse() {
+                Ok(r) => Some((Mask::from(&binding.mask[..]), r)),
+                Err(e) => {
+                    warn!("ignoring role binding for {}: {}", binding.mask, e);
+                    None
+                }
+            }
+        }).collect::<Vec<_>>();
+
+        Ok(self.bindings.iter().chain(stored.iter())
+            .filter(|&&(ref m, _)| m.matches(hostmask, account))
+            .map(|&(_, r)| r)
+            .max()
+            .unwrap_or(Role::Everyone))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs() {
+        assert!(glob_matches("*!*@example.com", "alice!al@example.com"));
+        assert!(glob_matches("a*b*c", "aXbYbZc"));
+        assert!(!glob_matches("a*b*c", "aXbYbZ"));
+        assert!(glob_matches("a?c", "abc"));
+        assert!(!glob_matches("a?c", "ac"));
+        assert!(!glob_matches("*.example.com", "example.com"));
+        assert!(glob_matches("**", ""));
+        assert!(glob_matches("", ""));
+        assert!(!glob_matches("", "x"));
+        // case is ignored the way the server ignores it
+        assert!(glob_matches("[Alice]!*@*", "{aLICE}!al@example.com"));
+    }
+
+    #[test]
+    fn masks() {
+        let hostmask = Some("alice!al@example.com");
+        assert!(Mask::from("$a:Alice").matches(hostmask, Some("alice")));
+        assert!(!Mask::from("$a:alice").matches(hostmask, None));
+        assert!(!Mask::from("$a:alice").matches(hostmask, Some("mallory")));
+        assert!(Mask::from("*!*@example.com").matches(hostmask, None));
+        assert!(!Mask::from("*!*@example.org").matches(hostmask, Some("alice")));
+    }
+
+    #[test]
+    fn bare_owner_nicknames_are_accounts() {
+        let config = Config { owners: Some(vec!["Alice".to_owned()]), .. Config::default() };
+        let owner = &Permissions::new(&config, SqliteConnection::establish(":memory:").unwrap())
+            .bindings[0].0;
+        assert_eq!(*owner, Mask::Account("Alice".to_owned()));
+        // taking the owner's nickname without identifying is not enough
+        assert!(!owner.matches(Some("alice!m@example.com"), None));
+        assert!(owner.matches(Some("alice_!al@example.com"), Some("alice")));
+    }
+}
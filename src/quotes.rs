@@ -0,0 +1,76 @@
+use chrono::Utc;
+use diesel;
+use diesel::prelude::*;
+use diesel::result::{Error as QueryError};
+use diesel::sql_types::{BigInt, Text};
+use diesel::sqlite::SqliteConnection;
+use rand::{self, Rng};
+
+use models::{NewQuote, Quote};
+
+/// Memorable lines from each channel.
+pub struct Quotes {
+    conn: SqliteConnection,
+}
+
+impl From<SqliteConnection> for Quotes {
+    fn from(conn: SqliteConnection) -> Quotes {
+        Quotes { conn }
+    }
+}
+
+impl Quotes {
+    /// Adds a quote, returning its number.
+    pub fn add(
+        &self, channel: &str, nickname: Option<&str>, text: &str, added_by: &str,
+    ) -> QueryResult<i32> {
+        use schema::quotes::dsl;
+
+        self.conn.transaction::<_, QueryError, _>(|| {
+            diesel::insert_into(dsl::quotes).values(&NewQuote {
+                channel,
+                nickname,
+                text,
+                added_by,
+                added: &Utc::now().naive_utc(),
+            }).execute(&self.conn)?;
+            dsl::quotes.select(dsl::id).order(dsl::id.desc()).first(&self.conn)
+        })
+    }
+
+    pub fn get(&self, id: i32) -> QueryResult<Option<Quote>> {
+        use schema::quotes::dsl;
+
+        dsl::quotes.find(id).first(&self.conn).optional()
+    }
+
+    /// Picks a quote at random from those added in `channel`.
+    pub fn random(&self, channel: &str) -> QueryResult<Option<Quote>> {
+        use schema::quotes::dsl;
+
+        let in_channel = dsl::quotes.filter(dsl::channel.eq(channel));
+        let count = in_channel.count().get_result::<i64>(&self.conn)?;
+        if count == 0 {
+            return Ok(None);
+        }
+
+        let offset = rand::thread_rng().gen_range(0, count);
+        in_channel.order(dsl::id).offset(offset).first(&self.conn).optional()
+    }
+
+    /// Finds quotes added in `channel` containing `text`, ignoring case, newest first.
+    pub fn search(&self, channel: &str, text: &str, limit: i64) -> QueryResult<Vec<Quote>> {
+        // instr rather than LIKE, so that nothing in the text is taken for a wildcard
+        diesel::sql_query(
+            "SELECT * FROM quotes WHERE channel = ? AND instr(lower(text), lower(?)) > 0 \
+             ORDER BY id DESC LIMIT ?"
+        ).bind::<Text, _>(channel).bind::<Text, _>(text).bind::<BigInt, _>(limit).load(&self.conn)
+    }
+
+    /// Removes a quote, returning whether there was one to remove.
+    pub fn remove(&self, id: i32) -> QueryResult<bool> {
+        use schema::quotes::dsl;
+
+        diesel::delete(dsl::quotes.find(id)).execute(&self.conn).map(|deleted| deleted > 0)
+    }
+}
@@ -0,0 +1,121 @@
+use std::cell::Cell;
+
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel;
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use irc::client::prelude::*;
+use irc::error::Result;
+use irc::error::IrcError::Custom;
+
+use models::{time_ago_str, NewReminder, Reminder};
+use nick;
+
+/// Pending reminders, stored in the database so that they survive restarts. The time of the
+/// next one is cached so that checking for due reminders is usually free.
+pub struct Reminders {
+    conn: SqliteConnection,
+    next_due: Cell<Option<NaiveDateTime>>,
+}
+
+impl Reminders {
+    /// Creates the store, picking up any reminders left pending from before a restart.
+    pub fn load(conn: SqliteConnection) -> QueryResult<Reminders> {
+        let reminders = Reminders { conn, next_due: Cell::new(None) };
+        reminders.refresh()?;
+        Ok(reminders)
+    }
+
+    pub fn add(&self, reminder: &NewReminder) -> QueryResult<()> {
+        use schema::reminders;
+
+        diesel::insert_into(reminders::table).values(reminder).execute(&self.conn)?;
+        self.refresh()
+    }
+
+    /// Lists the pending reminders set by `creator`, soonest first.
+    pub fn created_by(&self, creator: &str) -> QueryResult<Vec<Reminder>> {
+        use schema::reminders::dsl;
+
+        dsl::reminders
+            .filter(dsl::creator_key.eq(nick::fold(creator)))
+            .order(dsl::due)
+            .load(&self.conn)
+    }
+
+    /// Cancels a reminder set by `creator`, returning whether there was one to cancel.
+    pub fn cancel(&self, creator: &str, id: i32) -> QueryResult<bool> {
+        use schema::reminders::dsl;
+
+        let deleted = diesel::delete(
+            dsl::reminders.filter(dsl::id.eq(id)).filter(dsl::creator_key.eq(nick::fold(creator)))
+        ).execute(&self.conn)?;
+        self.refresh()?;
+        Ok(deleted > 0)
+    }
+
+    /// Sends every reminder that is due. This is called on every tick of the scheduler.
+    pub fn fire(&self, client: &IrcClient) -> Result<()> {
+        use schema::reminders::dsl;
+
+        let now = Utc::now().naive_utc();
+        match self.next_due.get() {
+            Some(next) if next <= now => (),
+            _ => return Ok(()),
+        }
+
+        let due = dsl::reminders
+            .filter(dsl::due.le(now))
+            .order(dsl::due)
+            .load::<Reminder>(&self.conn)
+            .map_err(|e| Custom { inner: e.into() })?;
+
+        for reminder in &due {
+            // the server would drop anything sent to a channel we haven't joined yet, so those
+            // wait until we're back in it
+            if !can_send(client, reminder.destination()) {
+                continue;
+            }
+
+            // reminders missed while we were disconnected are sent late, but say so
+            if now.signed_duration_since(reminder.due) > Duration::minutes(1) {
+                client.send_privmsg(reminder.destination(), format!(
+                    "{} (this was due {})", reminder, time_ago_str(reminder.due).to_lowercase()
+                ))?;
+            } else {
+                client.send_privmsg(reminder.destination(), format!("{}", reminder))?;
+            }
+
+            // each one is removed as soon as it's sent so that a failure partway through doesn't
+            // send the earlier ones again
+            diesel::delete(dsl::reminders.find(reminder.id))
+                .execute(&self.conn)
+                .map_err(|e| Custom { inner: e.into() })?;
+        }
+
+        self.refresh().map_err(|e| Custom { inner: e.into() })
+    }
+
+    fn refresh(&self) -> QueryResult<()> {
+        use schema::reminders::dsl;
+
+        let next = dsl::reminders
+            .select(dsl::due)
+            .order(dsl::due)
+            .first::<NaiveDateTime>(&self.conn)
+            .optional()?;
+        self.next_due.set(next);
+        Ok(())
+    }
+}
+
+/// Checks whether a message to `target` would be delivered: channels must have been joined, and
+/// anyone else can be messaged whenever we're connected.
+pub fn can_send(client: &IrcClient, target: &str) -> bool {
+    if !target.starts_with('#') && !target.starts_with('&') {
+        return true;
+    }
+    client.list_channels()
+        .map(|joined| joined.iter().any(|chan| nick::eq(chan, target)))
+        .unwrap_or(false)
+}
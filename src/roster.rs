@@ -0,0 +1,494 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use diesel;
+use diesel::prelude::*;
+use diesel::result::{Error as QueryError};
+use diesel::sqlite::SqliteConnection;
+use irc::client::prelude::*;
+
+use nick::{self, Nickname};
+
+/// Channel prefixes that grant operator status, from founder down to op.
+const OP_PREFIXES: &[char] = &['~', '&', '@'];
+const ALL_PREFIXES: &[char] = &['~', '&', '@', '%', '+'];
+
+/// What we know about a user, independent of the channels they are in.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct User {
+    pub nickname: String,
+    pub username: Option<String>,
+    pub hostname: Option<String>,
+    pub account: Option<String>,
+    pub away: bool,
+    /// Whether this was restored from the database and the server hasn't confirmed it since, so
+    /// that its account can't be trusted.
+    pub stale: bool,
+}
+
+impl User {
+    /// Drops what was restored from the database about a stale user, now that they've been seen.
+    fn refresh(&mut self) {
+        if self.stale {
+            *self = User { nickname: self.nickname.clone(), .. User::default() };
+        }
+    }
+}
+
+/// A user in a channel, along with their channel prefixes (e.g. `@` or `+`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Member {
+    pub user: User,
+    pub modes: String,
+}
+
+impl Member {
+    pub fn is_op(&self) -> bool {
+        self.modes.contains(OP_PREFIXES)
+    }
+}
+
+/// Tracks who is in each channel we are in, using `NAMES` and periodic `WHO` replies along with
+/// `JOIN`, `PART`, `QUIT`, `NICK`, `KICK`, and `MODE` messages. When backed by a database, each
+/// member is persisted whenever they change, and those restored from it are stale until the
+/// server tells us about them again. Nicknames and channel names are compared using the
+/// server's casemapping.
+#[derive(Default)]
+pub struct Roster {
+    conn: Option<SqliteConnection>,
+    users: RefCell<HashMap<Nickname, User>>,
+    channels: RefCell<HashMap<Nickname, HashMap<Nickname, String>>>,
+    pending: RefCell<HashMap<Nickname, Vec<(User, String)>>>,
+    names: RefCell<HashMap<Nickname, HashMap<Nickname, String>>>,
+}
+
+impl Roster {
+    /// Creates a roster backed by the database, starting from whatever was last persisted.
+    pub fn load(conn: SqliteConnection) -> QueryResult<Roster> {
+        use models::RosterEntry;
+        use schema::roster;
+
+        let roster = Roster::default();
+        for entry in roster::table.load::<RosterEntry>(&conn)? {
+            roster.channels.borrow_mut()
+                .entry(Nickname::from(entry.channel))
+                .or_insert_with(HashMap::new)
+                .insert(Nickname::from(&entry.nickname[..]), entry.modes);
+            roster.users.borrow_mut().insert(Nickname::from(&entry.nickname[..]), User {
+                nickname: entry.nickname,
+                username: entry.username,
+                hostname: entry.hostname,
+                account: entry.account,
+                away: entry.away,
+                stale: true,
+            });
+        }
+
+        Ok(Roster { conn: Some(conn), .. roster })
+    }
+
+    /// Gets what we know about the user with the given nickname.
+    pub fn user(&self, nickname: &str) -> Option<User> {
+        self.users.borrow().get(&Nickname::from(nickname)).cloned()
+    }
+
+    /// Lists everyone currently in `channel`.
+    pub fn members(&self, channel: &str) -> Vec<Member> {
+        let users = self.users.borrow();
+        let mut members: Vec<_> = self.channels.borrow().get(&Nickname::from(channel)).map(|chan| {
+            chan.iter().map(|(nick, modes)| Member {
+                user: users.get(nick).cloned().unwrap_or_else(|| User {
+                    nickname: nick.to_string(),
+                    .. User::default()
+                }),
+                modes: modes.to_owned(),
+            }).collect()
+        }).unwrap_or_else(Vec::new);
+        members.sort_by(|a, b| a.user.nickname.cmp(&b.user.nickname));
+        members
+    }
+
+    /// Finds a specific user in `channel`.
+    pub fn member(&self, channel: &str, nickname: &str) -> Option<Member> {
+        let modes = self.channels.borrow()
+            .get(&Nickname::from(channel))?
+            .get(&Nickname::from(nickname))?
+            .to_owned();
+        Some(Member {
+            user: self.user(nickname).unwrap_or_else(|| User {
+                nickname: nickname.to_owned(),
+                .. User::default()
+            }),
+            modes,
+        })
+    }
+
+    /// Checks whether we are in `channel`.
+    pub fn is_joined(&self, channel: &str) -> bool {
+        self.channels.borrow().contains_key(&Nickname::from(channel))
+    }
+
+    pub fn is_present(&self, channel: &str, nickname: &str) -> bool {
+        self.member(channel, nickname).is_some()
+    }
+
+    pub fn is_op(&self, channel: &str, nickname: &str) -> bool {
+        self.member(channel, nickname).map(|m| m.is_op()).unwrap_or(false)
+    }
+
+    /// Lists the nicknames of everyone holding operator status in `channel`.
+    pub fn ops(&self, channel: &str) -> Vec<String> {
+        self.members(channel).into_iter()
+            .filter(|m| m.is_op())
+            .map(|m| m.user.nickname)
+            .collect()
+    }
+
+    /// Lists every channel `nickname` shares with us.
+    pub fn channels_of(&self, nickname: &str) -> Vec<String> {
+        let nickname = Nickname::from(nickname);
+        let mut channels: Vec<_> = self.channels.borrow().iter().filter(|&(_, members)| {
+            members.contains_key(&nickname)
+        }).map(|(chan, _)| chan.to_string()).collect();
+        channels.sort();
+        channels
+    }
+
+    /// Folds every nickname and channel name again after the server's casemapping has changed.
+    pub fn refold(&self) {
+        fn refold<V>(map: &mut HashMap<Nickname, V>) {
+            *map = map.drain().map(|(mut name, value)| {
+                name.refold();
+                (name, value)
+            }).collect();
+        }
+
+        refold(&mut self.users.borrow_mut());
+        refold(&mut self.pending.borrow_mut());
+        for members in self.names.borrow_mut().values_mut() {
+            refold(members);
+        }
+        refold(&mut self.names.borrow_mut());
+        let mut channels = self.channels.borrow_mut();
+        refold(&mut channels);
+        for members in channels.values_mut() {
+            refold(members);
+        }
+    }
+
+    /// Updates the roster from a message received from the server.
+    pub fn update(&self, client: &IrcClient, message: &Message) {
+        let (nick, username, hostname) = match message.prefix {
+            Some(ref prefix) => split_prefix(prefix),
+            None => ("", None, None),
+        };
+        let ours = nick::eq(nick, client.current_nickname());
+
+        match message.command {
+            Command::JOIN(ref chanlist, ref account, ref realname) => {
+                self.saw(nick, username, hostname);
+                // with extended-join, the account name takes the place of the channel key
+                if realname.is_some() {
+                    self.set_account(nick, account.as_ref().map(|s| &s[..]));
+                }
+
+                for chan in chanlist.split(',') {
+                    {
+                        let mut channels = self.channels.borrow_mut();
+                        if ours {
+                            channels.insert(Nickname::from(chan), HashMap::new());
+                        }
+                        channels.entry(Nickname::from(chan))
+                            .or_insert_with(HashMap::new)
+                            .insert(Nickname::from(nick), String::new());
+                    }
+                    if ours {
+                        self.persist_channel(chan);
+                    } else {
+                        self.persist_member(chan, nick);
+                    }
+                }
+            }
+            Command::PART(ref chanlist, _) => for chan in chanlist.split(',') {
+                self.remove(chan, nick, ours);
+            },
+            Command::KICK(ref chanlist, ref target, _) => for chan in chanlist.split(',') {
+                self.remove(chan, target, nick::eq(target, client.current_nickname()));
+            },
+            Command::QUIT(_) => for chan in self.channels_of(nick) {
+                self.remove(&chan, nick, false);
+            },
+            Command::NICK(ref new_nick) => self.rename(nick, new_nick),
+            Command::ChannelMODE(ref chan, ref modes) => for mode in modes {
+                let (set, mode, target) = match *mode {
+                    Mode::Plus(ref mode, Some(ref target)) => (true, mode, target),
+                    Mode::Minus(ref mode, Some(ref target)) => (false, mode, target),
+                    _ => continue,
+                };
+                if let Some(prefix) = prefix_of(mode) {
+                    self.set_prefix(chan, target, prefix, set);
+                }
+            },
+            Command::ACCOUNT(ref account) => self.set_account(nick, Some(&account[..])),
+            Command::Raw(..) => if let Some((nick, account)) = whois_account(message) {
+                self.set_account(nick, Some(account));
+            },
+            Command::AWAY(ref reason) => {
+                if let Some(user) = self.users.borrow_mut().get_mut(&Nickname::from(nick)) {
+                    user.refresh();
+                    user.away = reason.is_some();
+                }
+            }
+            Command::Response(Response::RPL_NAMREPLY, ref args, Some(ref names))
+                if args.len() >= 3 => {
+                let mut pending = self.names.borrow_mut();
+                let members = pending.entry(Nickname::from(&args[2][..]))
+                    .or_insert_with(HashMap::new);
+                let mut users = self.users.borrow_mut();
+                for name in names.split_whitespace() {
+                    let nick = name.trim_left_matches(ALL_PREFIXES);
+                    let modes = &name[..name.len() - nick.len()];
+                    members.insert(Nickname::from(nick), modes.to_owned());
+                    users.entry(Nickname::from(nick)).or_insert_with(|| User {
+                        nickname: nick.to_owned(),
+                        .. User::default()
+                    }).refresh();
+                }
+            }
+            Command::Response(Response::RPL_ENDOFNAMES, ref args, _) if args.len() >= 2 => {
+                let chan = &args[1];
+                let members = self.names.borrow_mut()
+                    .remove(&Nickname::from(&chan[..]))
+                    .unwrap_or_else(HashMap::new);
+                self.channels.borrow_mut().insert(Nickname::from(&chan[..]), members);
+                self.persist_channel(chan);
+            }
+            Command::Response(Response::RPL_WHOREPLY, ref args, _) if args.len() >= 7 => {
+                let flags = &args[6];
+                // WHO doesn't say which account someone is using, so a restored one is dropped
+                let account = self.user(&args[5])
+                    .and_then(|user| if user.stale { None } else { user.account });
+                let user = User {
+                    nickname: args[5].to_owned(),
+                    username: Some(args[2].to_owned()),
+                    hostname: Some(args[3].to_owned()),
+                    account,
+                    away: flags.starts_with('G'),
+                    stale: false,
+                };
+                let modes = flags.chars().filter(|c| ALL_PREFIXES.contains(c)).collect();
+                self.pending.borrow_mut()
+                    .entry(Nickname::from(&args[1][..]))
+                    .or_insert_with(Vec::new)
+                    .push((user, modes));
+            }
+            Command::Response(Response::RPL_ENDOFWHO, ref args, _) if args.len() >= 2 => {
+                let chan = &args[1];
+                let replies = self.pending.borrow_mut()
+                    .remove(&Nickname::from(&chan[..]))
+                    .unwrap_or_else(Vec::new);
+                {
+                    let mut users = self.users.borrow_mut();
+                    let mut members = HashMap::new();
+                    for (user, modes) in replies {
+                        members.insert(Nickname::from(&user.nickname[..]), modes);
+                        users.insert(Nickname::from(&user.nickname[..]), user);
+                    }
+                    self.channels.borrow_mut().insert(Nickname::from(&chan[..]), members);
+                }
+                self.persist_channel(chan);
+            }
+            _ => (),
+        }
+    }
+
+    fn saw(&self, nick: &str, username: Option<&str>, hostname: Option<&str>) {
+        let mut users = self.users.borrow_mut();
+        let user = users.entry(Nickname::from(nick)).or_insert_with(|| User {
+            nickname: nick.to_owned(),
+            .. User::default()
+        });
+        user.refresh();
+        if let Some(username) = username {
+            user.username = Some(username.to_owned());
+        }
+        if let Some(hostname) = hostname {
+            user.hostname = Some(hostname.to_owned());
+        }
+    }
+
+    fn set_account(&self, nick: &str, account: Option<&str>) {
+        if let Some(user) = self.users.borrow_mut().get_mut(&Nickname::from(nick)) {
+            user.refresh();
+            // `*` means that the user is not logged in
+            user.account = account.and_then(|a| if a == "*" { None } else { Some(a.to_owned()) });
+        }
+    }
+
+    /// Gives `nick` the channel prefix `prefix` in `chan`, or takes it away, keeping their
+    /// prefixes in order of rank.
+    fn set_prefix(&self, chan: &str, nick: &str, prefix: char, set: bool) {
+        {
+            let mut channels = self.channels.borrow_mut();
+            let modes = match channels.get_mut(&Nickname::from(chan))
+                .and_then(|members| members.get_mut(&Nickname::from(nick))) {
+                Some(modes) => modes,
+                None => return,
+            };
+            *modes = ALL_PREFIXES.iter()
+                .filter(|&&c| if c == prefix { set } else { modes.contains(c) })
+                .collect();
+        }
+        self.persist_member(chan, nick);
+    }
+
+    fn remove(&self, chan: &str, nick: &str, ours: bool) {
+        // when we leave, everyone else there goes with it unless we share another channel
+        let mut gone = vec![nick.to_owned()];
+        if ours {
+            if let Some(members) = self.channels.borrow_mut().remove(&Nickname::from(chan)) {
+                gone.extend(members.keys().map(|member| member.to_string()));
+            }
+        } else if let Some(members) = self.channels.borrow_mut().get_mut(&Nickname::from(chan)) {
+            members.remove(&Nickname::from(nick));
+        }
+
+        for member in &gone {
+            if self.channels_of(member).is_empty() {
+                self.users.borrow_mut().remove(&Nickname::from(&member[..]));
+            }
+        }
+        if ours {
+            self.persist_channel(chan);
+        } else {
+            self.persist_member(chan, nick);
+        }
+    }
+
+    fn rename(&self, old: &str, new: &str) {
+        let channels = self.channels_of(old);
+        {
+            let mut users = self.users.borrow_mut();
+            if let Some(mut user) = users.remove(&Nickname::from(old)) {
+                user.nickname = new.to_owned();
+                users.insert(Nickname::from(new), user);
+            }
+
+            for members in self.channels.borrow_mut().values_mut() {
+                if let Some(modes) = members.remove(&Nickname::from(old)) {
+                    members.insert(Nickname::from(new), modes);
+                }
+            }
+        }
+
+        for chan in &channels {
+            self.persist_member(chan, old);
+            self.persist_member(chan, new);
+        }
+    }
+
+    /// Replaces the stored copy of `chan` with what is in memory, after learning everyone in it
+    /// at once.
+    fn persist_channel(&self, chan: &str) {
+        use schema::roster;
+
+        let conn = match self.conn {
+            Some(ref conn) => conn,
+            None => return,
+        };
+
+        let members = self.members(chan);
+        let channel_key = nick::fold(chan);
+        let result = conn.transaction::<_, QueryError, _>(|| {
+            diesel::delete(roster::table.filter(roster::channel_key.eq(&channel_key[..])))
+                .execute(conn)?;
+            for member in &members {
+                store(conn, chan, member)?;
+            }
+            Ok(())
+        });
+
+        if let Err(e) = result {
+            error!("failed to persist the roster for {}: {}", chan, e);
+        }
+    }
+
+    /// Replaces the stored copy of `nick` in `chan` with what is in memory, removing it if they
+    /// aren't there anymore.
+    fn persist_member(&self, chan: &str, nick: &str) {
+        use schema::roster;
+
+        let conn = match self.conn {
+            Some(ref conn) => conn,
+            None => return,
+        };
+
+        let result = match self.member(chan, nick) {
+            Some(member) => store(conn, chan, &member),
+            None => {
+                let (channel_key, nickname_key) = (nick::fold(chan), nick::fold(nick));
+                diesel::delete(roster::table.find((&channel_key[..], &nickname_key[..])))
+                    .execute(conn)
+            }
+        };
+
+        if let Err(e) = result {
+            error!("failed to persist {} in the roster for {}: {}", nick, chan, e);
+        }
+    }
+}
+
+/// Stores `member` of `chan`, replacing whatever was stored about them there before.
+fn store(conn: &SqliteConnection, chan: &str, member: &Member) -> QueryResult<usize> {
+    use models::NewRosterEntry;
+    use schema::roster;
+
+    let user = &member.user;
+    diesel::replace_into(roster::table).values(&NewRosterEntry {
+        channel: chan,
+        nickname: &user.nickname,
+        username: user.username.as_ref().map(|s| &s[..]),
+        hostname: user.hostname.as_ref().map(|s| &s[..]),
+        account: user.account.as_ref().map(|s| &s[..]),
+        away: user.away,
+        modes: &member.modes,
+        channel_key: &nick::fold(chan),
+        nickname_key: &nick::fold(&user.nickname),
+    }).execute(conn)
+}
+
+/// Finds the channel prefix that goes with a channel mode, if it's one held by a member.
+fn prefix_of(mode: &ChannelMode) -> Option<char> {
+    match *mode {
+        ChannelMode::Founder => Some('~'),
+        ChannelMode::Admin => Some('&'),
+        ChannelMode::Oper => Some('@'),
+        ChannelMode::Halfop => Some('%'),
+        ChannelMode::Voice => Some('+'),
+        _ => None,
+    }
+}
+
+/// Splits a `nick!user@host` prefix into its parts. Server prefixes are returned as a nickname.
+pub fn split_prefix(prefix: &str) -> (&str, Option<&str>, Option<&str>) {
+    let (nick, rest) = match prefix.find('!') {
+        Some(idx) => (&prefix[..idx], Some(&prefix[idx + 1..])),
+        None => (prefix, None),
+    };
+
+    match rest.and_then(|rest| rest.find('@').map(|idx| (rest, idx))) {
+        Some((rest, idx)) => (nick, Some(&rest[..idx]), Some(&rest[idx + 1..])),
+        None => (nick, rest, None),
+    }
+}
+
+/// Reads the nickname and account from a `WHOIS` reply saying which account someone is logged
+/// in to (numeric 330, which the irc crate doesn't know by name).
+pub fn whois_account(message: &Message) -> Option<(&str, &str)> {
+    match message.command {
+        Command::Raw(ref code, ref args, _) if code == "330" && args.len() >= 3 => {
+            Some((&args[1], &args[2]))
+        }
+        _ => None,
+    }
+}
@@ -1,3 +1,56 @@
+table! {
+    announcements (id) {
+        id -> Integer,
+        creator -> Text,
+        channel -> Text,
+        schedule -> Text,
+        message -> Text,
+        next_run -> Timestamp,
+        creator_key -> Text,
+    }
+}
+
+table! {
+    casemapping (id) {
+        id -> Integer,
+        mapping -> Text,
+    }
+}
+
+table! {
+    factoid_history (id) {
+        id -> Integer,
+        channel -> Text,
+        name -> Text,
+        verb -> Text,
+        value -> Nullable<Text>,
+        changed_by -> Text,
+        changed -> Timestamp,
+    }
+}
+
+table! {
+    factoids (channel, name) {
+        channel -> Text,
+        name -> Text,
+        verb -> Text,
+        value -> Text,
+        created_by -> Text,
+        created -> Timestamp,
+        locked -> Bool,
+    }
+}
+
+table! {
+    karma (channel, thing_key) {
+        channel -> Text,
+        thing_key -> Text,
+        thing -> Text,
+        score -> Integer,
+        updated -> Timestamp,
+    }
+}
+
 table! {
     mail (id) {
         id -> Integer,
@@ -6,17 +59,147 @@ table! {
         message -> Text,
         sent -> Timestamp,
         private -> Bool,
+        deliver_after -> Nullable<Timestamp>,
+        request_receipt -> Bool,
+        receipt -> Bool,
+        expires -> Nullable<Timestamp>,
+        target_key -> Text,
+        sender_key -> Text,
+    }
+}
+
+table! {
+    nick_links (nickname_key) {
+        nickname -> Text,
+        person -> Text,
+        account -> Nullable<Text>,
+        linked -> Timestamp,
+        nickname_key -> Text,
+    }
+}
+
+table! {
+    quotes (id) {
+        id -> Integer,
+        channel -> Text,
+        nickname -> Nullable<Text>,
+        text -> Text,
+        added_by -> Text,
+        added -> Timestamp,
+    }
+}
+
+table! {
+    reminders (id) {
+        id -> Integer,
+        creator -> Text,
+        target -> Text,
+        channel -> Text,
+        message -> Text,
+        created -> Timestamp,
+        due -> Timestamp,
+        creator_key -> Text,
+    }
+}
+
+table! {
+    roles (mask) {
+        mask -> Text,
+        role -> Text,
     }
 }
 
 table! {
-    whois (nickname) {
+    roster (channel_key, nickname_key) {
+        channel -> Text,
+        nickname -> Text,
+        username -> Nullable<Text>,
+        hostname -> Nullable<Text>,
+        account -> Nullable<Text>,
+        away -> Bool,
+        modes -> Text,
+        channel_key -> Text,
+        nickname_key -> Text,
+    }
+}
+
+table! {
+    seen (nickname_key) {
+        nickname -> Text,
+        action -> Text,
+        channel -> Nullable<Text>,
+        detail -> Nullable<Text>,
+        time -> Timestamp,
+        nickname_key -> Text,
+    }
+}
+
+table! {
+    whois (nickname_key) {
         nickname -> Text,
         description -> Text,
+        nickname_key -> Text,
+        account -> Nullable<Text>,
+    }
+}
+
+table! {
+    whois_audit (id) {
+        id -> Integer,
+        nickname_key -> Text,
+        nickname -> Text,
+        action -> Text,
+        detail -> Nullable<Text>,
+        changed_by -> Text,
+        changed -> Timestamp,
+    }
+}
+
+table! {
+    whois_facts (nickname_key, name) {
+        nickname_key -> Text,
+        name -> Text,
+        nickname -> Text,
+        value -> Text,
+        account -> Nullable<Text>,
+    }
+}
+
+table! {
+    whois_locks (nickname_key) {
+        nickname_key -> Text,
+        locked_by -> Text,
+        locked -> Timestamp,
+    }
+}
+
+table! {
+    whois_history (id) {
+        id -> Integer,
+        nickname_key -> Text,
+        nickname -> Text,
+        description -> Nullable<Text>,
+        changed_by -> Text,
+        changed -> Timestamp,
     }
 }
 
 allow_tables_to_appear_in_same_query!(
+    announcements,
+    casemapping,
+    factoid_history,
+    factoids,
+    karma,
     mail,
+    nick_links,
+    quotes,
+    reminders,
+    roles,
+    roster,
+    seen,
     whois,
+    whois_audit,
+    whois_facts,
+    whois_history,
+    whois_locks,
 );
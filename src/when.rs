@@ -0,0 +1,237 @@
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime};
+
+/// The furthest into the future that a duration may reach, to keep arithmetic in range.
+const MAX_SECONDS: i64 = 100 * 365 * 24 * 60 * 60;
+
+/// Parses a description of a moment from the start of `words`, returning it along with the
+/// number of words it took up. All times are in UTC. The following forms are understood:
+///
+/// - `in 2h`, `in 1h30m`, `in 20 minutes`, `in an hour`
+/// - `at 17:00`, `at 5pm` (today, or tomorrow if that time has already passed)
+/// - `at 2026-11-01`, `at 2026-11-01 09:00`, `at 2026-11-01T09:00`, and the same with `on`
+pub fn parse_time(words: &[&str], now: NaiveDateTime) -> Option<(NaiveDateTime, usize)> {
+    let first = words.first()?.to_lowercase();
+    match &first[..] {
+        "in" => {
+            let (duration, used) = parse_duration(&words[1..])?;
+            Some((now + duration, used + 1))
+        }
+        "at" | "on" => {
+            let (time, used) = parse_moment(&words[1..], now)?;
+            Some((time, used + 1))
+        }
+        _ => None,
+    }
+}
+
+/// Parses a length of time from the start of `words`, written either compactly (`1h30m`) or in
+/// words (`2 hours 30 minutes`, `a day`), returning it along with the number of words used.
+pub fn parse_duration(words: &[&str]) -> Option<(Duration, usize)> {
+    let mut total: i64 = 0;
+    let mut used = 0;
+
+    while used < words.len() {
+        let word = words[used].to_lowercase();
+        let (seconds, len) = if let Some(seconds) = compact_duration(&word) {
+            (seconds, 1)
+        } else {
+            let count = match &word[..] {
+                "a" | "an" => Some(1),
+                word => word.parse::<i64>().ok(),
+            };
+            let unit = words.get(used + 1).and_then(|unit| unit_seconds(&unit.to_lowercase()));
+            match (count, unit) {
+                (Some(count), Some(unit)) => (count.checked_mul(unit)?, 2),
+                _ => break,
+            }
+        };
+
+        total = total.checked_add(seconds)?;
+        used += len;
+    }
+
+    if used == 0 || total <= 0 || total > MAX_SECONDS {
+        None
+    } else {
+        Some((Duration::seconds(total), used))
+    }
+}
+
+/// Parses a time of day like `17:00`, `9:05:30`, `5pm`, or `5:30am`.
+pub fn parse_clock(word: &str) -> Option<NaiveTime> {
+    let word = word.to_lowercase();
+    let (digits, offset) = if word.ends_with("am") {
+        (&word[..word.len() - 2], Some(0))
+    } else if word.ends_with("pm") {
+        (&word[..word.len() - 2], Some(12))
+    } else {
+        (&word[..], None)
+    };
+
+    let mut parts = digits.split(':');
+    let hour: u32 = parts.next()?.parse().ok()?;
+    let minute: u32 = match parts.next() {
+        Some(minute) => minute.parse().ok()?,
+        // a bare number is only a time with am or pm after it
+        None if offset.is_some() => 0,
+        None => return None,
+    };
+    let second: u32 = match parts.next() {
+        Some(second) => second.parse().ok()?,
+        None => 0,
+    };
+    if parts.next().is_some() {
+        return None;
+    }
+
+    let hour = match offset {
+        Some(_) if hour == 0 || hour > 12 => return None,
+        Some(offset) => hour % 12 + offset,
+        None => hour,
+    };
+    NaiveTime::from_hms_opt(hour, minute, second)
+}
+
+/// Formats a moment the same way it is accepted by `parse_time`.
+pub fn describe(time: NaiveDateTime) -> String {
+    time.format("%Y-%m-%d %H:%M UTC").to_string()
+}
+
+/// Parses `<date> [time]`, `<date>T<time>`, or `<time>`.
+fn parse_moment(words: &[&str], now: NaiveDateTime) -> Option<(NaiveDateTime, usize)> {
+    let first = words.first()?;
+
+    if let Some(idx) = first.find('T') {
+        let date = NaiveDate::parse_from_str(&first[..idx], "%Y-%m-%d");
+        if let (Ok(date), Some(time)) = (date, parse_clock(&first[idx + 1..])) {
+            return Some((date.and_time(time), 1));
+        }
+    }
+
+    if let Ok(date) = NaiveDate::parse_from_str(first, "%Y-%m-%d") {
+        match words.get(1).and_then(|word| parse_clock(word)) {
+            Some(time) => Some((date.and_time(time), 2)),
+            None => Some((date.and_hms(0, 0, 0), 1)),
+        }
+    } else {
+        let today = now.date().and_time(parse_clock(first)?);
+        Some((if today > now { today } else { today + Duration::days(1) }, 1))
+    }
+}
+
+/// Parses durations like `90s` or `1h30m`.
+fn compact_duration(word: &str) -> Option<i64> {
+    if word.is_empty() {
+        return None;
+    }
+
+    let mut total: i64 = 0;
+    let mut rest = word;
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_digit(10)).unwrap_or(rest.len());
+        if digits == 0 {
+            return None;
+        }
+        let count: i64 = rest[..digits].parse().ok()?;
+        rest = &rest[digits..];
+
+        let letters = rest.find(|c: char| !c.is_alphabetic()).unwrap_or(rest.len());
+        total = total.checked_add(count.checked_mul(unit_seconds(&rest[..letters])?)?)?;
+        rest = &rest[letters..];
+    }
+
+    Some(total)
+}
+
+fn unit_seconds(unit: &str) -> Option<i64> {
+    match unit {
+        "s" | "sec" | "secs" | "second" | "seconds" => Some(1),
+        "m" | "min" | "mins" | "minute" | "minutes" => Some(60),
+        "h" | "hr" | "hrs" | "hour" | "hours" => Some(60 * 60),
+        "d" | "day" | "days" => Some(24 * 60 * 60),
+        "w" | "wk" | "wks" | "week" | "weeks" => Some(7 * 24 * 60 * 60),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn noon() -> NaiveDateTime {
+        NaiveDate::from_ymd(2026, 10, 18).and_hms(12, 0, 0)
+    }
+
+    #[test]
+    fn relative_times() {
+        let now = noon();
+        assert_eq!(parse_time(&["in", "2h"], now), Some((now + Duration::hours(2), 2)));
+        assert_eq!(parse_time(&["in", "1h30m", "hi"], now), Some((now + Duration::minutes(90), 2)));
+        assert_eq!(
+            parse_time(&["in", "20", "minutes", "hi"], now),
+            Some((now + Duration::minutes(20), 3))
+        );
+        assert_eq!(parse_time(&["in", "an", "hour"], now), Some((now + Duration::hours(1), 3)));
+        assert_eq!(parse_time(&["In", "2H"], now), Some((now + Duration::hours(2), 2)));
+        assert_eq!(parse_time(&["in", "soon"], now), None);
+        assert_eq!(parse_time(&["in"], now), None);
+    }
+
+    #[test]
+    fn clock_times_roll_over_to_tomorrow() {
+        let now = noon();
+        let today = now.date();
+        assert_eq!(parse_time(&["at", "17:00"], now), Some((today.and_hms(17, 0, 0), 2)));
+        assert_eq!(parse_time(&["at", "5pm"], now), Some((today.and_hms(17, 0, 0), 2)));
+        assert_eq!(parse_time(&["at", "9:00"], now), Some((today.succ().and_hms(9, 0, 0), 2)));
+        assert_eq!(parse_time(&["at", "12:00"], now), Some((today.succ().and_hms(12, 0, 0), 2)));
+    }
+
+    #[test]
+    fn dates() {
+        let now = noon();
+        let date = NaiveDate::from_ymd(2026, 11, 1);
+        assert_eq!(
+            parse_time(&["at", "2026-11-01", "09:00", "hi"], now),
+            Some((date.and_hms(9, 0, 0), 3))
+        );
+        assert_eq!(parse_time(&["at", "2026-11-01T09:00"], now), Some((date.and_hms(9, 0, 0), 2)));
+        assert_eq!(parse_time(&["on", "2026-11-01", "hi"], now), Some((date.and_hms(0, 0, 0), 2)));
+        assert_eq!(parse_time(&["on", "2026-13-01"], now), None);
+    }
+
+    #[test]
+    fn words_without_a_keyword_are_not_times() {
+        let now = noon();
+        // "tell bob tomorrow is release day" is a message, not a delivery time
+        assert_eq!(parse_time(&["tomorrow", "is", "release", "day"], now), None);
+        assert_eq!(parse_time(&["tomorrow", "at", "5pm"], now), None);
+        assert_eq!(parse_time(&["2h"], now), None);
+        assert_eq!(parse_time(&[], now), None);
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration(&["90s"]), Some((Duration::seconds(90), 1)));
+        assert_eq!(parse_duration(&["1w2d"]), Some((Duration::days(9), 1)));
+        assert_eq!(parse_duration(&["1h", "30", "minutes"]), Some((Duration::minutes(90), 3)));
+        assert_eq!(parse_duration(&["0s"]), None);
+        assert_eq!(parse_duration(&["5x"]), None);
+        assert_eq!(parse_duration(&["h"]), None);
+        assert_eq!(parse_duration(&["2"]), None);
+        assert_eq!(parse_duration(&["99999999999w"]), None);
+    }
+
+    #[test]
+    fn clocks() {
+        assert_eq!(parse_clock("9:05:30"), Some(NaiveTime::from_hms(9, 5, 30)));
+        assert_eq!(parse_clock("12am"), Some(NaiveTime::from_hms(0, 0, 0)));
+        assert_eq!(parse_clock("12pm"), Some(NaiveTime::from_hms(12, 0, 0)));
+        assert_eq!(parse_clock("5:30PM"), Some(NaiveTime::from_hms(17, 30, 0)));
+        assert_eq!(parse_clock("13pm"), None);
+        assert_eq!(parse_clock("0am"), None);
+        assert_eq!(parse_clock("5"), None);
+        assert_eq!(parse_clock("24:00"), None);
+        assert_eq!(parse_clock("1:2:3:4"), None);
+    }
+}